@@ -0,0 +1,28 @@
+use std::convert::TryInto;
+
+use proc_macro::{TokenStream, TokenTree};
+use shared::unquote;
+
+/// Parses `N` comma separated paths, and nothing else.
+pub fn parse_paths<const N: usize>(input: TokenStream) -> Result<[String; N], String> {
+    let mut paths = Vec::new();
+    let mut expect_comma = false;
+    for token in input {
+        match token {
+            TokenTree::Punct(p) if p.as_char() == ',' && expect_comma => expect_comma = false,
+            TokenTree::Literal(lit) if !expect_comma => {
+                let lit = lit.to_string();
+                paths.push(
+                    unquote(&lit, '"').ok_or_else(|| format!("Expected a path, found {}", lit))?,
+                );
+                expect_comma = true;
+            }
+            token => return Err(format!("Expected a path, found {}", token)),
+        }
+    }
+
+    let found = paths.len();
+    paths
+        .try_into()
+        .map_err(|_| format!("Expected {} paths, found {}", N, found))
+}
@@ -0,0 +1,213 @@
+//! Packs a text input into an archive at compile time, so the firmware doesn't depend on the
+//! host having been run first.
+
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use proc_macro::{Literal, TokenStream};
+use shared::{parse_preload, ArchiveSpec, ArchiveSpecs, Reader, SharedDictionary};
+
+mod args;
+
+/// Reads a text input, packs it with `shared::Writer`, and evaluates to the archive as a
+/// `&'static [u8; N]`, the same as `include_bytes!` would.
+///
+/// Takes the path to a spec file, relative to the root of the crate using the macro, then the
+/// input as the spec file names it. The spec file says how to split each input into records, and
+/// is read by the host too, so that the preloads and shared dictionary it finds suit the archives
+/// packed here. See `shared::ArchiveSpecs` for the options. If the spec file names a shared
+/// dictionary, compressing against it is tried as well, and archives which do need
+/// `Reader::open_with_dict` with the dictionary from `include_dictionary!`.
+///
+/// A preload or dictionary which doesn't exist yet is treated as empty.
+///
+/// ```ignore
+/// let archive = include_archive!("../archives.spec", "inputs/aoc_1902.txt");
+/// ```
+#[proc_macro]
+pub fn include_archive(input: TokenStream) -> TokenStream {
+    report_errors(expand_archive(input))
+}
+
+/// Evaluates to the shared dictionary named by a spec file, as written by the host, for
+/// `Reader::open_with_dict`, after checking that it reads. If there isn't one yet, evaluates to an
+/// empty slice, which is all archives need that don't use it.
+///
+/// ```ignore
+/// const DICTIONARY: &[u8] = include_dictionary!("../archives.spec");
+/// ```
+#[proc_macro]
+pub fn include_dictionary(input: TokenStream) -> TokenStream {
+    report_errors(expand_dictionary(input))
+}
+
+fn report_errors(result: Result<TokenStream, String>) -> TokenStream {
+    match result {
+        Ok(tokens) => tokens,
+        Err(msg) => format!("compile_error!({:?})", msg).parse().unwrap(),
+    }
+}
+
+// Files read while expanding a macro, relative to the root of the crate using it, so that they
+// can be included in the expansion as well.
+struct Files {
+    root: PathBuf,
+    read: Vec<PathBuf>,
+}
+
+impl Files {
+    fn new() -> Result<Self, String> {
+        let root = env::var("CARGO_MANIFEST_DIR").map_err(|e| e.to_string())?;
+        Ok(Self {
+            root: root.into(),
+            read: Vec::new(),
+        })
+    }
+
+    fn read(&mut self, path: &Path) -> Result<Vec<u8>, String> {
+        self.read_optional(path)?
+            .ok_or_else(|| format!("Can't find {}", self.root.join(path).display()))
+    }
+
+    // Returns `None` if the file doesn't exist.
+    fn read_optional(&mut self, path: &Path) -> Result<Option<Vec<u8>>, String> {
+        let path = self.root.join(path);
+        match fs::read(&path) {
+            Ok(contents) => {
+                self.read.push(path);
+                Ok(Some(contents))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Can't read {}: {}", path.display(), e)),
+        }
+    }
+
+    // Reads a spec file, along with the directory the paths in it are relative to.
+    fn read_specs(&mut self, path: &str) -> Result<(ArchiveSpecs, PathBuf), String> {
+        let text = String::from_utf8(self.read(Path::new(path))?)
+            .map_err(|_| format!("{} isn't valid UTF-8", path))?;
+        let specs = ArchiveSpecs::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Ok((specs, dir.to_owned()))
+    }
+
+    // Wraps `bytes` in a block which also includes every file read, so that the crate is rebuilt
+    // whenever one of them changes.
+    fn expand(&self, bytes: &[u8]) -> Result<TokenStream, String> {
+        let mut tokens = "{".to_owned();
+        for path in &self.read {
+            let path = path
+                .to_str()
+                .ok_or_else(|| format!("Path isn't valid UTF-8: {}", path.display()))?;
+            tokens += &format!("const _: &[u8] = include_bytes!({:?});", path);
+        }
+        tokens += &format!("{} }}", Literal::byte_string(bytes));
+
+        tokens.parse().map_err(|e| format!("{:?}", e))
+    }
+}
+
+fn expand_archive(input: TokenStream) -> Result<TokenStream, String> {
+    let [spec_path, input] = args::parse_paths(input)?;
+    let mut files = Files::new()?;
+    let (specs, dir) = files.read_specs(&spec_path)?;
+    let spec = specs
+        .get(&input)
+        .ok_or_else(|| format!("{} has no spec for {}", spec_path, input))?;
+
+    let text = String::from_utf8(files.read(&dir.join(&input))?)
+        .map_err(|_| format!("{} isn't valid UTF-8", input))?;
+
+    let mut preload = Vec::new();
+    if let Some(path) = &spec.preload_file {
+        if let Some(contents) = files.read_optional(&dir.join(path))? {
+            preload = std::str::from_utf8(&contents)
+                .ok()
+                .and_then(parse_preload)
+                .ok_or_else(|| format!("Invalid preload file: {}", path))?;
+        }
+    }
+
+    let dict = match &specs.dict {
+        Some(path) => files.read_optional(&dir.join(path))?,
+        None => None,
+    };
+
+    let archive = pack(spec, &text, &preload, dict.as_deref())?;
+    files.expand(&archive)
+}
+
+fn expand_dictionary(input: TokenStream) -> Result<TokenStream, String> {
+    let [spec_path] = args::parse_paths(input)?;
+    let mut files = Files::new()?;
+    let (specs, dir) = files.read_specs(&spec_path)?;
+
+    let dict = match &specs.dict {
+        Some(path) => files.read_optional(&dir.join(path))?.unwrap_or_default(),
+        None => Vec::new(),
+    };
+    if !dict.is_empty() {
+        SharedDictionary::load(&dict).map_err(|e| format!("Invalid dictionary: {}", e))?;
+    }
+    files.expand(&dict)
+}
+
+fn pack(
+    spec: &ArchiveSpec,
+    text: &str,
+    preload: &[Vec<u8>],
+    dict: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let mut archive = spec.writer(text, preload)?;
+
+    let shared_dict = dict
+        .map(SharedDictionary::load)
+        .transpose()
+        .map_err(|e| format!("Invalid dictionary: {}", e))?;
+    if let Some(shared_dict) = &shared_dict {
+        archive.use_shared_dictionary(shared_dict);
+    }
+
+    let mut output = Vec::new();
+    archive.write(&mut output).map_err(|e| e.to_string())?;
+    Reader::open_with_dict(&output, dict.unwrap_or_default())
+        .and_then(|reader| reader.validate())
+        .map_err(|e| format!("Archive doesn't read back: {}", e))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::CompressOptions;
+
+    #[test]
+    fn pack_with_dictionary() {
+        let text = "R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83\n";
+        let spec = ArchiveSpec {
+            delimiter: Some(','),
+            ..ArchiveSpec::new("")
+        };
+        let records = text.lines().flat_map(|line| line.split(','));
+        let dict =
+            SharedDictionary::train(CompressOptions::new(), records.clone().map(str::as_bytes))
+                .store();
+
+        let archive = pack(&spec, text, &[], Some(&dict)).unwrap();
+        assert!(Reader::open(&archive).is_err());
+
+        let mut buf = [0; 8];
+        let mut reader = Reader::open_with_dict(&archive, &dict).unwrap();
+        for record in records {
+            assert_eq!(
+                reader.next_record(&mut buf).unwrap(),
+                Some(record.as_bytes())
+            );
+        }
+
+        assert!(pack(&spec, text, &[], Some(&dict[1..])).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+# How each day's input is packed, read by both the host and `include_archive!` in the firmware.
+# Paths are relative to this file. See `shared::ArchiveSpecs` for the options.
+
+# Dictionary shared between the archives, trained by the host along with their preloads.
+dict = "inputs/dict.bin"
+
+# Integers are stored as a few bytes of LEB128 each, so long entries are wasted.
+"inputs/aoc_1901.txt", kind = u64, max_entry_len = 3, preload = "inputs/aoc_1901.preload"
+"inputs/aoc_1902.txt", kind = u64, delimiter = ',', max_entry_len = 2, preload = "inputs/aoc_1902.preload"
+
+# Only a handful of distinct characters, which Huffman coding single bytes does better with than a
+# dictionary of sequences does.
+"inputs/aoc_1903.txt", sections = ["wire 1", "wire 2"], delimiter = ',', dict_size = 0, preload = "inputs/aoc_1903.preload"
+
+"inputs/aoc_1904.txt", preload = "inputs/aoc_1904.preload"
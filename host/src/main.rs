@@ -1,98 +1,153 @@
 use std::{
     fs::{read_to_string, File},
-    io::ErrorKind,
+    io::{ErrorKind, Write},
 };
 
-use shared::Writer;
+use shared::{
+    escape_preload_entry, ArchiveSpec, ArchiveSpecs, CompressOptions, CompressionLevel, Reader,
+    RecordKind, SharedDictionary, Writer,
+};
+
+mod preload;
+mod report;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-fn archive_input<F>(day: u8, dict_preload: &[&str], record_func: F) -> Result<()>
-where
-    F: for<'a> Fn(&mut Writer<'a>, &'a str) -> String,
-{
-    print!("Day {}... ", day);
-
-    let contents = match read_to_string(format!("../inputs/aoc_19{:02}.txt", day)) {
-        Ok(f) => f,
-        Err(e) if e.kind() == ErrorKind::NotFound => {
-            println!("Input not found.");
-            return Ok(());
-        }
-        Err(e) => return Err(e.into()),
-    };
+// Says how each input is packed. The board's archives are packed from the same file by
+// `include_archive!`, as the host is only here to find the preloads and shared dictionary those
+// read.
+const SPEC_PATH: &str = "../archives.spec";
 
-    let mut archive = Writer::new();
-    archive.preload_dict(dict_preload);
-    let msg = record_func(&mut archive, &contents);
+// Paths in the spec file are relative to it.
+fn spec_relative(path: &str) -> String {
+    format!("../{}", path)
+}
 
-    let output_file = File::create(format!("../inputs/aoc_19{:02}.bin", day))?;
-    archive.write(output_file).unwrap();
+// Writes `archive`, then reads all of it back to make sure that works. `dict` is only used by
+// archives compressed against a shared dictionary.
+fn write_archive(archive: &Writer, dict: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    archive.write(&mut output)?;
+    Reader::open_with_dict(&output, dict)?.validate()?;
+    Ok(output)
+}
 
-    println!(" Written. {}", msg);
+// Searches for the preload which gives the smallest archive for `spec`, and reports what it
+// found.
+fn optimize_preload(spec: &ArchiveSpec, contents: &str) -> Result<Vec<Vec<u8>>> {
+    let archive_len = |preload: &[Vec<u8>]| -> Result<usize> {
+        Ok(write_archive(&spec.writer(contents, preload)?, &[])?.len())
+    };
 
-    Ok(())
+    let archive = spec.writer(contents, &[])?;
+    let candidates = preload::candidates(archive.records());
+    let start_len = archive_len(&[])?;
+    let (preload, len) = preload::search(&candidates, archive_len)?;
+
+    let entries: Vec<_> = preload
+        .iter()
+        .map(|entry| format!("\"{}\"", escape_preload_entry(entry)))
+        .collect();
+    println!(
+        "{}... Tried {} candidates. Preloading [{}] takes the archive from {} to {} bytes",
+        spec.input,
+        candidates.len(),
+        entries.join(", "),
+        start_len,
+        len
+    );
+
+    Ok(preload)
 }
 
-fn by_line<'a>(archive: &mut Writer<'a>, input: &'a str) -> String {
-    let max_len = input.lines().fold(0, |max_len, record| {
-        archive.add_record(record);
-        max_len.max(record.len())
-    });
-
-    format!("Longest record: {} bytes", max_len)
+fn read_input(spec: &ArchiveSpec) -> Result<Option<String>> {
+    match read_to_string(spec_relative(&spec.input)) {
+        Ok(f) => Ok(Some(f)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
 
-fn intcode_split<'a>(archive: &mut Writer<'a>, input: &'a str) -> String {
-    // This one should print the maximum address used, as well as the longest record.
+fn main() -> Result<()> {
+    let specs = ArchiveSpecs::parse(&read_to_string(SPEC_PATH)?)
+        .map_err(|e| format!("{}: {}", SPEC_PATH, e))?;
+
+    // Searching for the best preload takes a while, so it's only done when asked for. The
+    // result is recorded where the spec says, so that later runs give the same archive.
+    let search_preloads = std::env::args().any(|arg| arg == "--optimize-preload");
+
+    let mut inputs = Vec::new();
+    for spec in &specs.archives {
+        let contents = match read_input(spec)? {
+            Some(contents) => contents,
+            None => {
+                println!("{}... Input not found.", spec.input);
+                continue;
+            }
+        };
+
+        let preload = match &spec.preload_file {
+            Some(path) if search_preloads => {
+                let preload = optimize_preload(spec, &contents)?;
+                preload::save(&spec_relative(path), &preload)?;
+                preload
+            }
+            Some(path) => preload::load(&spec_relative(path))?.unwrap_or_default(),
+            None => Vec::new(),
+        };
+
+        inputs.push((spec, contents, preload));
+    }
 
-    let mut max_address = 0;
-    let mut longest_record = 0;
+    let mut archives = Vec::new();
+    for (spec, contents, preload) in &inputs {
+        archives.push(spec.writer(contents, preload)?);
+    }
 
-    for (i, num) in input.split(',').enumerate() {
-        max_address = i;
-        longest_record = longest_record.max(num.trim().len());
-        archive.add_record(num);
+    let mut outputs = Vec::new();
+    for archive in &archives {
+        outputs.push(write_archive(archive, &[])?);
     }
 
-    format!(
-        "Max Addr: {}, Longest Record: {} bytes",
-        max_address, longest_record
-    )
-}
+    let mut stored_dict = Vec::new();
+    if let Some(dict_path) = &specs.dict {
+        // Many days share vocabulary, such as digits and direction letters, so one dictionary is
+        // trained on all of them and stored once. Each archive only uses it if that comes out
+        // smaller than a dictionary of its own. Integers are stored as LEB128, which has nothing
+        // in common with text, so only days of text records are trained on.
+        let options = CompressOptions::new().level(CompressionLevel::Optimal);
+        let dictionary = SharedDictionary::train(
+            options,
+            archives
+                .iter()
+                .filter(|archive| archive.record_kind() == RecordKind::Bytes)
+                .flat_map(Writer::records),
+        );
+
+        stored_dict = dictionary.store();
+        let mut shared_dict_outputs = Vec::new();
+        for archive in &mut archives {
+            archive.use_shared_dictionary(&dictionary);
+            shared_dict_outputs.push(write_archive(archive, &stored_dict)?);
+        }
 
-fn day_3<'a>(archive: &mut Writer<'a>, input: &'a str) -> String {
-    let mut lines = input.lines();
-    let first_wire = lines.next().unwrap();
-    let second_wire = lines.next().unwrap();
-
-    let mut max_len = 0;
-    let mut num_instrs_first_wire = 0;
-    for instr in first_wire.split(',') {
-        archive.add_record(instr);
-        num_instrs_first_wire += 1;
-        max_len = instr.len().max(max_len);
-    }
+        // The dictionary takes up flash too, so it's only kept if it saves more than its own
+        // size.
+        let total_len = |outputs: &[Vec<u8>]| outputs.iter().map(Vec::len).sum::<usize>();
+        if stored_dict.len() + total_len(&shared_dict_outputs) < total_len(&outputs) {
+            outputs = shared_dict_outputs;
+        } else {
+            stored_dict = SharedDictionary::train(options, std::iter::empty()).store();
+        }
 
-    archive.add_record("-");
-    let mut num_instrs_second_wire = 0;
-    for instr in second_wire.split(',') {
-        archive.add_record(instr);
-        num_instrs_second_wire += 1;
-        max_len = instr.len().max(max_len);
+        File::create(spec_relative(dict_path))?.write_all(&stored_dict)?;
+        println!("Dictionary... Written. {} bytes", stored_dict.len());
     }
 
-    format!(
-        "Max record: {} bytes, Wire 1 Length: {}, Wire 2 Length: {}",
-        max_len, num_instrs_first_wire, num_instrs_second_wire
-    )
-}
-
-fn main() -> Result<()> {
-    archive_input(1, &[], by_line)?;
-    archive_input(2, &["1,", "2,", "99,"], intcode_split)?;
-    archive_input(3, &[], day_3)?;
-    archive_input(4, &[], by_line)?;
+    for ((spec, _, _), output) in inputs.iter().zip(outputs) {
+        println!("{}...", spec.input);
+        report::print_info(&output, &stored_dict);
+    }
 
     Ok(())
 }
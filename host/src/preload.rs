@@ -0,0 +1,102 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, File},
+    io::{ErrorKind, Write},
+};
+
+use shared::{format_preload, parse_preload};
+
+use crate::Result;
+
+// How many of the best scoring candidates the search tries.
+const MAX_CANDIDATES: usize = 24;
+// Longest n-gram considered as a candidate.
+const MAX_NGRAM_LEN: usize = 8;
+
+/// Finds sequences which may be worth preloading: delimiter-bounded tokens, with and without the
+/// delimiter, and frequent n-grams. The ones which look like they'd save the most come first.
+pub fn candidates<'r>(records: impl Iterator<Item = &'r [u8]>) -> Vec<Vec<u8>> {
+    let mut counts: BTreeMap<&[u8], usize> = BTreeMap::new();
+    for record in records {
+        let mut token_start = 0;
+        for (i, b) in record.iter().enumerate() {
+            if b.is_ascii_punctuation() || b.is_ascii_whitespace() {
+                *counts.entry(&record[token_start..i]).or_default() += 1;
+                *counts.entry(&record[token_start..=i]).or_default() += 1;
+                token_start = i + 1;
+            }
+        }
+        *counts.entry(&record[token_start..]).or_default() += 1;
+
+        for len in 2..=MAX_NGRAM_LEN {
+            for ngram in record.windows(len) {
+                *counts.entry(ngram).or_default() += 1;
+            }
+        }
+    }
+
+    // Anything seen once is better left to the compressor, and single bytes are always in the
+    // dictionary anyway.
+    let mut candidates: Vec<_> = counts
+        .into_iter()
+        .filter(|&(seq, count)| seq.len() > 1 && count > 1)
+        .collect();
+    candidates.sort_by_key(|&(seq, count)| std::cmp::Reverse((count - 1) * seq.len()));
+
+    candidates
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(seq, _)| seq.to_vec())
+        .collect()
+}
+
+/// Builds up a preload one candidate at a time, each round adding whichever makes the archive
+/// smallest, until none of them help. Returns the preload and the archive length it gives.
+pub fn search(
+    candidates: &[Vec<u8>],
+    mut archive_len: impl FnMut(&[Vec<u8>]) -> Result<usize>,
+) -> Result<(Vec<Vec<u8>>, usize)> {
+    let mut preload = Vec::new();
+    let mut best_len = archive_len(&preload)?;
+
+    let mut remaining = candidates.to_vec();
+    loop {
+        let mut best_candidate = None;
+        for (i, candidate) in remaining.iter().enumerate() {
+            preload.push(candidate.clone());
+            let len = archive_len(&preload)?;
+            preload.pop();
+
+            if len < best_len {
+                best_len = len;
+                best_candidate = Some(i);
+            }
+        }
+
+        match best_candidate {
+            Some(i) => preload.push(remaining.remove(i)),
+            None => return Ok((preload, best_len)),
+        }
+    }
+}
+
+/// Reads a preload written by `save`, returning `None` if there isn't one.
+pub fn load(path: &str) -> Result<Option<Vec<Vec<u8>>>> {
+    let contents = match read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    match parse_preload(&contents) {
+        Some(entries) => Ok(Some(entries)),
+        None => Err(format!("Invalid preload file: {}", path).into()),
+    }
+}
+
+/// Writes `preload` one entry per line, escaping anything that isn't printable ASCII, which is
+/// also how `include_archive!` reads it.
+pub fn save(path: &str, preload: &[Vec<u8>]) -> Result<()> {
+    File::create(path)?.write_all(format_preload(preload).as_bytes())?;
+    Ok(())
+}
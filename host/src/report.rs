@@ -0,0 +1,78 @@
+use shared::{ArchiveInfo, Codec};
+
+// Bytes of each region shown in the header dump. Longer regions are cut short.
+const MAX_DUMP_LEN: usize = 16;
+
+/// Prints what's in `archive`, and a hex dump of everything before the records annotated with
+/// what each part is.
+pub fn print_info(archive: &[u8], dict: &[u8]) {
+    let info = match ArchiveInfo::read_with_dict(archive, dict) {
+        Ok(info) => info,
+        Err(e) => {
+            println!("    Can't read archive: {}", e);
+            return;
+        }
+    };
+
+    println!(
+        "    {:?} codec, version {}, flags {:#010b}, {} {:?} records in {} sections",
+        info.codec, info.version, info.flags, info.num_records, info.record_kind, info.num_sections
+    );
+
+    if let Some(limits) = info.limits {
+        println!(
+            "    Longest record {} bytes, largest decoded {} bytes",
+            limits.max_record_len, limits.max_decoded_len
+        );
+    }
+
+    if info.dict_entries > 0 {
+        let location = if info.codec == Codec::SharedDictionary {
+            " (shared)"
+        } else {
+            ""
+        };
+        println!(
+            "    Dictionary{}: {} entries, {} bytes, lookup table {} bytes",
+            location, info.dict_entries, info.dict_len, info.lookup_len
+        );
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let mean_tokens = info.tokens.total as f64 / info.num_records.max(1) as f64;
+    println!(
+        "    Records: {} of {} bytes, {} to {} tokens each, {:.1} on average",
+        info.records_len, info.total_len, info.tokens.min, info.tokens.max, mean_tokens
+    );
+
+    for (name, region) in info.regions() {
+        if name == "records" || name == "checksum" {
+            println!(
+                "    {:06x}  {:<52} {} ({} bytes)",
+                region.start,
+                "",
+                name,
+                region.len()
+            );
+            continue;
+        }
+
+        let bytes = &archive[region.clone()];
+        let mut hex: Vec<_> = bytes
+            .iter()
+            .take(MAX_DUMP_LEN)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        if bytes.len() > MAX_DUMP_LEN {
+            hex.push("..".to_owned());
+        }
+
+        println!(
+            "    {:06x}  {:<52} {} ({} bytes)",
+            region.start,
+            hex.join(" "),
+            name,
+            region.len()
+        );
+    }
+}
@@ -4,6 +4,8 @@ use core::{
 };
 
 use crate::rtc::RTC;
+use archive_macro::include_dictionary;
+use shared::Reader;
 use ssd1306::{displaysize::DisplaySize128x64, mode::TerminalMode, prelude::I2CInterface};
 use stm32f3_discovery::stm32f3xx_hal::{
     delay::Delay,
@@ -22,14 +24,18 @@ mod day3;
 mod day4;
 mod intcode;
 
+// Dictionary shared between the archives, trained by the host along with their preloads.
+const DICTIONARY: &[u8] = include_dictionary!("../archives.spec");
+
 type Interface = I2CInterface<I2c<I2C1, (PB6<AF4>, PB7<AF4>)>>;
 type Terminal = TerminalMode<Interface, DisplaySize128x64>;
 
-const CHALLENGES: &[(u8, fn(&RTC) -> ChallengeResponse)] = &[
-    (1, day1::run),
-    (2, day2::run),
-    (3, day3::run),
-    (4, day4::run),
+// Each day, with the archive holding its input.
+const CHALLENGES: &[(u8, fn(&RTC) -> ChallengeResponse, &[u8])] = &[
+    (1, day1::run, day1::INPUT),
+    (2, day2::run, day2::INPUT),
+    (3, day3::run, day3::INPUT),
+    (4, day4::run, day4::INPUT),
 ];
 
 pub struct ChallengeResponse {
@@ -43,7 +49,18 @@ pub fn run(delayer: &mut Delay, rtc: &RTC, display: &mut Terminal) {
     let _ = display.clear();
     let _ = display.write_str("    AoC 2019\r\n\n");
 
-    for (i, challenge) in CHALLENGES {
+    // Checking every input up front means a bad one shows up here, rather than as a panic or a
+    // wrong answer part way through the challenges.
+    for (i, _, input) in CHALLENGES {
+        if let Err(e) =
+            Reader::open_with_dict(input, DICTIONARY).and_then(|reader| reader.validate())
+        {
+            let _ = writeln!(display, "Day {} input: {}", i, e);
+            return;
+        }
+    }
+
+    for (i, challenge, _) in CHALLENGES {
         let _ = write!(display, "Day {}", i);
         let ChallengeResponse {
             duration,
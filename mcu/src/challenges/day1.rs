@@ -1,19 +1,20 @@
-use super::ChallengeResponse;
+use super::{ChallengeResponse, DICTIONARY};
 use crate::rtc::RTC;
 
+use archive_macro::include_archive;
 use shared::Reader;
 
+pub(super) const INPUT: &[u8] = include_archive!("../archives.spec", "inputs/aoc_1901.txt");
+
 pub fn run(rtc: &RTC) -> ChallengeResponse {
-    let mut input = Reader::open(include_bytes!("../../../inputs/aoc_1901.bin")).unwrap();
+    let mut input = Reader::open_with_dict(INPUT, DICTIONARY).unwrap();
 
     // Part 1
     let start = rtc.now();
 
     let mut sum_p1 = 0;
     let mut sum_p2 = 0;
-    let mut buf = [0; 6];
-    while let Some(record) = input.next_record(&mut buf).unwrap() {
-        let mass: u64 = core::str::from_utf8(record).unwrap().parse().unwrap();
+    for mass in input.u64_records().map(Result::unwrap) {
         sum_p1 += mass / 3 - 2;
 
         let mut new_fuel = 0;
@@ -1,21 +1,27 @@
-use shared::Reader;
+use core::convert::TryInto;
 
-use super::{intcode::IntCode, ChallengeResponse};
+use archive_macro::include_archive;
+use shared::{ArchiveLimits, Reader};
+
+use super::{intcode::IntCode, ChallengeResponse, DICTIONARY};
 use crate::rtc::RTC;
 
+pub(super) const INPUT: &[u8] = include_archive!("../archives.spec", "inputs/aoc_1902.txt");
+const LIMITS: ArchiveLimits = ArchiveLimits::of(INPUT);
+// Memory is made of `u32`s, so the program has to fit in them.
+const _: () = assert!(LIMITS.max_decoded_len <= core::mem::size_of::<u32>());
+
 fn load_program(mem: &mut [u32]) {
-    let mut input = Reader::open(include_bytes!("../../../inputs/aoc_1902.bin")).unwrap();
-    let mut buf = [0; 3];
-    let mut mem = mem.iter_mut();
-    while let Some((record, dst)) = input.next_record(&mut buf).unwrap().zip(mem.next()) {
-        *dst = core::str::from_utf8(record).unwrap().parse().unwrap();
+    let mut input = Reader::open_with_dict(INPUT, DICTIONARY).unwrap();
+    for (dst, val) in mem.iter_mut().zip(input.u64_records()) {
+        *dst = val.unwrap().try_into().unwrap();
     }
 }
 
 pub fn run(rtc: &RTC) -> ChallengeResponse {
     let start = rtc.now();
 
-    let mut orig_memory = [0_u32; 140];
+    let mut orig_memory = [0_u32; LIMITS.num_records];
     load_program(&mut orig_memory);
 
     // Part 1
@@ -1,11 +1,17 @@
 use core::convert::TryInto;
 
-use shared::Reader;
-use tinyvec::ArrayVec;
+use archive_macro::include_archive;
+use shared::{ArchiveLimits, Reader};
+use tinyvec::{Array, ArrayVec};
 
-use super::ChallengeResponse;
+use super::{ChallengeResponse, DICTIONARY};
 use crate::rtc::RTC;
 
+pub(super) const INPUT: &[u8] = include_archive!("../archives.spec", "inputs/aoc_1903.txt");
+const LIMITS: ArchiveLimits = ArchiveLimits::of(INPUT);
+const WIRE1_LIMITS: ArchiveLimits = ArchiveLimits::of_section(INPUT, 0);
+const WIRE2_LIMITS: ArchiveLimits = ArchiveLimits::of_section(INPUT, 1);
+
 #[derive(Copy, Clone, Default)]
 struct Point {
     x: i16,
@@ -66,30 +72,12 @@ impl LineSegment {
     }
 }
 
-pub fn run(rtc: &RTC) -> ChallengeResponse {
-    let start = rtc.now();
-
-    let mut input = Reader::open(include_bytes!("../../../inputs/aoc_1903.bin")).unwrap();
-
-    let mut wire1_points = ArrayVec::<[Point; 302]>::new();
-    let mut wire2_points = ArrayVec::<[Point; 302]>::new();
+// Follows the turns of one wire from the origin, pushing each point it turns at onto `dst`.
+fn read_wire<A: Array<Item = Point>>(mut wire: Reader, buf: &mut [u8], dst: &mut ArrayVec<A>) {
     let mut cur_point = Point::default();
-    wire1_points.push(cur_point);
-    wire2_points.push(cur_point);
-
-    let mut buf = [0; 4];
-    let mut dst = &mut wire1_points;
-    while let Some(record) = input.next_record(&mut buf).unwrap() {
-        // End of wire 1 instructions.
-        if record == b"-" {
-            dst = &mut wire2_points;
-            cur_point = Point::default();
-            continue;
-        }
-
-        let magnitude: i16 = core::str::from_utf8(&record[1..]).unwrap().parse().unwrap();
-        let direction = record[0];
+    dst.push(cur_point);
 
+    while let Some((direction, magnitude)) = wire.next_parsed::<(u8, i16)>(buf).unwrap() {
         match direction {
             b'U' => cur_point.y -= magnitude,
             b'D' => cur_point.y += magnitude,
@@ -100,6 +88,22 @@ pub fn run(rtc: &RTC) -> ChallengeResponse {
 
         dst.push(cur_point);
     }
+}
+
+pub fn run(rtc: &RTC) -> ChallengeResponse {
+    let start = rtc.now();
+
+    let input = Reader::open_with_dict(INPUT, DICTIONARY).unwrap();
+
+    // Each wire has a point for every one of its records, plus the origin.
+    let mut wire1_points = ArrayVec::<[Point; WIRE1_LIMITS.num_records + 1]>::new();
+    let mut wire2_points = ArrayVec::<[Point; WIRE2_LIMITS.num_records + 1]>::new();
+
+    let mut buf = [0; LIMITS.max_record_len];
+    let wire1 = input.section(0).unwrap().unwrap();
+    read_wire(wire1, &mut buf, &mut wire1_points);
+    let wire2 = input.section(1).unwrap().unwrap();
+    read_wire(wire2, &mut buf, &mut wire2_points);
 
     // Part 1
     let mut closest = Point {
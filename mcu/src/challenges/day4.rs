@@ -1,9 +1,13 @@
+use archive_macro::include_archive;
 use itoa::Buffer;
-use shared::Reader;
+use shared::{ArchiveLimits, Fields, FromRecord, Reader};
 
-use super::ChallengeResponse;
+use super::{ChallengeResponse, DICTIONARY};
 use crate::rtc::RTC;
 
+pub(super) const INPUT: &[u8] = include_archive!("../archives.spec", "inputs/aoc_1904.txt");
+const LIMITS: ArchiveLimits = ArchiveLimits::of(INPUT);
+
 fn is_valid(password: u32) -> (bool, bool) {
     if password < 100_000 || password > 999_999 {
         return (false, false);
@@ -37,16 +41,13 @@ fn is_valid(password: u32) -> (bool, bool) {
 pub fn run(rtc: &RTC) -> ChallengeResponse {
     let start = rtc.now();
 
-    let mut input = Reader::open(include_bytes!("../../../inputs/aoc_1904.bin")).unwrap();
+    let mut input = Reader::open_with_dict(INPUT, DICTIONARY).unwrap();
 
-    let mut buf = [0; 16];
-    // Only one record.
+    let mut buf = [0; LIMITS.max_record_len];
+    // Only one record. Trimmed first, as the input may end in whitespace.
     let record = input.next_record(&mut buf).unwrap().unwrap();
-    let input = core::str::from_utf8(record).unwrap();
-
-    let (begin, end) = input.trim().split_once('-').unwrap();
-    let begin: u32 = begin.parse().unwrap();
-    let end: u32 = end.parse().unwrap();
+    let record = core::str::from_utf8(record).unwrap().trim();
+    let Fields((begin, end)) = Fields::<(u32, u32), b'-'>::from_record(record.as_bytes()).unwrap();
 
     let mut num_valid_p1 = 0;
     let mut num_valid_p2 = 0;
@@ -1,14 +1,136 @@
-use std::{collections::BTreeMap, convert::TryInto, io::Write};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
+    convert::TryInto,
+    io::Write,
+};
 
 use crate::{
-    RunLengthEncoded, ADDR_SIZE, DICT_START_ADDR, LOOKUP_START, NUM_RECORD_ADDR, RECORD_START_ADDR,
+    addr_width, crc::crc32, decompress::Decompress, write_addr, ErrorKind, Leb128, DICT_MAGIC,
+    HUFFMAN_MAX_CODE_LEN,
 };
 
+// Defaults for `CompressOptions`.
 const MAX_DICT_MULTI_BYTE_LEN: usize = 450;
 const MAX_DICT_ENTRY_LEN: usize = 10;
 
 type Id = usize;
-type SmallId = u16;
+type SmallId = u32;
+
+// A dictionary entry, and the number of times stage 2 saw it.
+type SeenEntry<'a> = (&'a [u8], usize);
+
+/// How hard the compressor works to shrink each record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    /// Splits each record by taking the first dictionary entry that matches at each point.
+    #[default]
+    Greedy,
+    /// Splits each record in the way that takes the fewest bytes to encode, then drops unused
+    /// dictionary entries and gives the most used ones the shortest ids. Slower than `Greedy`.
+    Optimal,
+}
+
+/// How stage 3 ranks the sequences seen in stage 2 when choosing which go in the dictionary.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SortHeuristic {
+    /// Times seen multiplied by length, roughly the number of bytes the entry saves.
+    #[default]
+    Savings,
+    /// Times seen, then length.
+    Count,
+    /// Length, then times seen.
+    Length,
+}
+
+impl SortHeuristic {
+    // Sequences with larger keys are ranked first.
+    fn key(self, seq: &[u8], count: usize) -> (usize, usize) {
+        match self {
+            Self::Savings => (count * seq.len(), 0),
+            Self::Count => (count, seq.len()),
+            Self::Length => (seq.len(), count),
+        }
+    }
+}
+
+/// Tuning for `Compress`, as the best settings depend on the input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CompressOptions {
+    dict_size: usize,
+    max_entry_len: usize,
+    sort_heuristic: SortHeuristic,
+    level: CompressionLevel,
+    allow_raw: bool,
+    allow_huffman: bool,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            dict_size: MAX_DICT_MULTI_BYTE_LEN,
+            max_entry_len: MAX_DICT_ENTRY_LEN,
+            sort_heuristic: SortHeuristic::Savings,
+            level: CompressionLevel::Greedy,
+            allow_raw: true,
+            allow_huffman: true,
+        }
+    }
+
+    /// Maximum number of entries stage 3 picks for the dictionary. Single bytes which aren't
+    /// covered by those entries are added on top.
+    #[must_use]
+    pub fn dict_size(mut self, dict_size: usize) -> Self {
+        self.dict_size = dict_size;
+        self
+    }
+
+    /// Maximum length of the sequences stage 1 adds to the dictionary. Entries added with
+    /// `Compress::add_dictionary_entry` may be longer.
+    #[must_use]
+    pub fn max_entry_len(mut self, max_entry_len: usize) -> Self {
+        self.max_entry_len = max_entry_len;
+        self
+    }
+
+    #[must_use]
+    pub fn sort_heuristic(mut self, sort_heuristic: SortHeuristic) -> Self {
+        self.sort_heuristic = sort_heuristic;
+        self
+    }
+
+    #[must_use]
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Whether `Writer` may store the records uncompressed when that's smaller.
+    #[must_use]
+    pub fn allow_raw(mut self, allow_raw: bool) -> Self {
+        self.allow_raw = allow_raw;
+        self
+    }
+
+    /// Whether `Writer` may Huffman code the dictionary ids when that's smaller. Huffman coded
+    /// records take a little longer to decode, as the codes aren't byte aligned.
+    #[must_use]
+    pub fn allow_huffman(mut self, allow_huffman: bool) -> Self {
+        self.allow_huffman = allow_huffman;
+        self
+    }
+
+    pub(crate) fn raw_allowed(&self) -> bool {
+        self.allow_raw
+    }
+}
 
 // Applies a 3-stage compression:
 //
@@ -29,7 +151,188 @@ pub struct Compress {
     dict: BTreeMap<Vec<u8>, Id>,
     // Uncompressed inputs.
     records: Vec<Vec<u8>>,
-    max_record_len: u32,
+    options: CompressOptions,
+}
+
+// Prefix tree over the stage 3 dictionary, so that finding which entries a record starts with
+// doesn't need to check every entry.
+#[derive(Debug)]
+struct Trie {
+    // Each node maps the next byte to its child, and may end a dictionary entry.
+    nodes: Vec<(BTreeMap<u8, usize>, Option<Id>)>,
+}
+
+impl Trie {
+    fn new() -> Self {
+        Self {
+            nodes: vec![(BTreeMap::new(), None)],
+        }
+    }
+
+    fn insert(&mut self, seq: &[u8], id: Id) {
+        let mut node = 0;
+        for &b in seq {
+            let next_node = self.nodes.len();
+            node = *self.nodes[node].0.entry(b).or_insert(next_node);
+            if node == next_node {
+                self.nodes.push((BTreeMap::new(), None));
+            }
+        }
+
+        self.nodes[node].1.get_or_insert(id);
+    }
+
+    // Yields the id and length of each entry `record` starts with, shortest first.
+    fn prefixes<'t>(&'t self, record: &'t [u8]) -> impl Iterator<Item = (Id, usize)> + 't {
+        let mut node = 0;
+        (1..)
+            .zip(record)
+            .map_while(move |(len, b)| {
+                node = *self.nodes[node].0.get(b)?;
+                Some((self.nodes[node].1, len))
+            })
+            .filter_map(|(id, len)| Some((id?, len)))
+    }
+
+    // Of the entries `record` starts with, returns the one with the lowest id, along with its
+    // length.
+    fn find_prefix(&self, record: &[u8]) -> Option<(Id, usize)> {
+        self.prefixes(record).min_by_key(|&(id, _)| id)
+    }
+
+    // Splits `record` into the entries that take the fewest bytes to encode as LEB128 ids, or
+    // returns `None` if part of it isn't covered by any entry. `cost` and `step` are only passed
+    // in so that they can be reused between records.
+    fn split_optimal(
+        &self,
+        record: &[u8],
+        cost: &mut Vec<Option<usize>>,
+        step: &mut Vec<(Id, usize)>,
+    ) -> Option<Vec<Id>> {
+        // Working backwards through the record, `cost[i]` is the fewest bytes `record[i..]` can
+        // be encoded in, and `step[i]` is the entry to start with to get there.
+        cost.clear();
+        cost.resize(record.len() + 1, None);
+        cost[record.len()] = Some(0);
+        step.clear();
+        step.resize(record.len(), (0, 0));
+
+        for i in (0..record.len()).rev() {
+            let best = self
+                .prefixes(&record[i..])
+                .filter_map(|(id, len)| {
+                    let id_len = Leb128::encode_usize(id).as_ref().len();
+                    Some((id_len + cost[i + len]?, (id, len)))
+                })
+                .min_by_key(|&(cost, _)| cost);
+
+            if let Some((best_cost, best_step)) = best {
+                cost[i] = Some(best_cost);
+                step[i] = best_step;
+            }
+        }
+
+        cost[0]?;
+        let mut ids = Vec::new();
+        let mut i = 0;
+        while i < record.len() {
+            let (id, len) = step[i];
+            ids.push(id);
+            i += len;
+        }
+
+        Some(ids)
+    }
+}
+
+/// A dictionary trained on the records of many archives, so that it can be stored once and
+/// shared between them rather than each archive carrying its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedDictionary {
+    // Most used first, so that they get the shortest ids.
+    entries: Vec<Vec<u8>>,
+}
+
+impl SharedDictionary {
+    /// Builds a dictionary from the entries `records` would be compressed with, using the
+    /// dictionary size and heuristics in `options`.
+    #[must_use]
+    pub fn train<'r>(
+        options: CompressOptions,
+        records: impl IntoIterator<Item = &'r [u8]>,
+    ) -> Self {
+        let mut compress = Compress::with_options(options);
+        for record in records {
+            compress.add_record(record);
+        }
+
+        let (final_dict, compressed_records) = compress.apply_stage3(compress.apply_stage2());
+        let mut uses = vec![0_usize; final_dict.len()];
+        for &id in compressed_records.iter().flatten() {
+            uses[id as usize] += 1;
+        }
+
+        let mut entries: Vec<_> = final_dict
+            .into_iter()
+            .filter(|&(_, id)| uses[id as usize] > 0)
+            .collect();
+        entries.sort_by_key(|&(_, id)| (Reverse(uses[id as usize]), id));
+
+        let mut entries: Vec<_> = entries.into_iter().map(|(entry, _)| entry).collect();
+
+        // Records compressed against the dictionary may not split the same way as the corpus,
+        // so every byte the corpus holds needs an entry of its own.
+        let bytes: BTreeSet<u8> = compress.records.iter().flatten().copied().collect();
+        for byte in bytes {
+            if !entries.iter().any(|entry| entry[..] == [byte]) {
+                entries.push(vec![byte]);
+            }
+        }
+
+        Self { entries }
+    }
+
+    /// Reads back a dictionary written by `store`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `stored` isn't a valid dictionary.
+    pub fn load(stored: &[u8]) -> Result<Self, ErrorKind> {
+        let (dict, _) = Decompress::open_shared_dictionary(stored)?;
+        let entries = dict
+            .dict_entries()
+            .map(|entry| entry.map(<[u8]>::to_vec))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+
+    #[must_use]
+    pub fn num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Stores the dictionary in a form that can be passed to `Reader::open_with_dict`.
+    #[must_use]
+    pub fn store(&self) -> Vec<u8> {
+        let mut dict = self.store_contents();
+        let checksum = crc32(&dict);
+        dict.extend_from_slice(&checksum.to_le_bytes());
+        dict
+    }
+
+    /// The id archives compressed against this dictionary store, which is the checksum of the
+    /// stored dictionary. Retraining with different records gives a different id.
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        crc32(&self.store_contents())
+    }
+
+    // Everything `store` writes but the checksum.
+    fn store_contents(&self) -> Vec<u8> {
+        let mut dict = DICT_MAGIC.to_vec();
+        dict.extend_from_slice(&store_dictionary(self.entries.iter().map(Vec::as_slice)));
+        dict
+    }
 }
 
 impl Default for Compress {
@@ -40,17 +343,31 @@ impl Default for Compress {
 
 impl Compress {
     // This code results in a lot of casting with the intention of truncating.
-    #![allow(clippy::clippy::cast_possible_truncation)]
+    #![allow(clippy::cast_possible_truncation)]
 
     #[must_use]
     pub fn new() -> Self {
+        Self::with_options(CompressOptions::new())
+    }
+
+    #[must_use]
+    pub fn with_options(options: CompressOptions) -> Self {
         Self {
             dict: (0..=255_u32).map(|b| (vec![b as u8], b as _)).collect(),
             records: Vec::new(),
-            max_record_len: 0,
+            options,
         }
     }
 
+    #[must_use]
+    pub fn options(&self) -> &CompressOptions {
+        &self.options
+    }
+
+    pub fn set_level(&mut self, level: CompressionLevel) {
+        self.options = self.options.level(level);
+    }
+
     pub fn add_dictionary_entry<T: AsRef<[u8]>>(&mut self, entry: T) {
         let entry = entry.as_ref();
 
@@ -63,43 +380,50 @@ impl Compress {
             }
 
             let val = self.dict.insert(e.to_vec(), self.dict.len());
-            if val.is_some() {
-                panic!("Incorrectly removed previous entry");
-            }
+            assert!(val.is_none(), "Incorrectly removed previous entry");
         }
     }
 
     pub fn add_record<T: AsRef<[u8]>>(&mut self, record: T) {
+        let record = record.as_ref();
+        self.add_sequences(record);
+
+        // Finally store the input for compression later.
+        self.records.push(record.to_owned());
+    }
+
+    /// Replaces record `n` with `record`. Sequences only the old record held are left in the
+    /// stage 1 dictionary, where stage 3 drops them for not being seen.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `n` records or fewer.
+    pub fn replace_record<T: AsRef<[u8]>>(&mut self, n: usize, record: T) {
+        let record = record.as_ref();
+        self.add_sequences(record);
+        record.clone_into(&mut self.records[n]);
+    }
+
+    fn add_sequences(&mut self, record: &[u8]) {
         // Applies stage 1 of the process above.
         // We only iterate over the input, not compress it here.
-        let record = record.as_ref();
 
         let mut cur_seq_start = 0;
 
         for idx in 0..record.len() {
             let new_seq = &record[cur_seq_start..=idx];
 
-            if !self.dict.contains_key(new_seq) || new_seq.len() >= MAX_DICT_ENTRY_LEN {
+            if !self.dict.contains_key(new_seq) || new_seq.len() >= self.options.max_entry_len {
                 // A bit odd, but we don't want to remove the previous entry.
                 if !self.dict.contains_key(new_seq) {
                     let val = self.dict.insert(new_seq.to_vec(), self.dict.len());
-
-                    if val.is_some() {
-                        panic!("Incorrectly removed previous entry");
-                    }
+                    assert!(val.is_none(), "Incorrectly removed previous entry");
                 }
 
                 // Restart the sequence starting with the current byte.
                 cur_seq_start = idx;
             }
         }
-
-        self.max_record_len = self
-            .max_record_len
-            .max(record.len().try_into().expect("Length too long"));
-
-        // Finally store the input for compression later.
-        self.records.push(record.to_owned());
     }
 
     fn apply_stage2(&self) -> BTreeMap<Id, usize> {
@@ -146,7 +470,7 @@ impl Compress {
 
         let id_to_seq: BTreeMap<Id, _> = self.dict.iter().map(|(a, b)| (*b, a)).collect();
 
-        let mut seen_entries: Vec<(&[u8], usize)> = counts
+        let mut seen_entries: Vec<SeenEntry> = counts
             .into_iter()
             .filter_map(|(id, count)| {
                 if count > 0 {
@@ -157,29 +481,59 @@ impl Compress {
             })
             .collect();
 
-        // We want to prioritise sequences that save us the most, so we want to prioritise
+        // By default we prioritise sequences that save us the most, so we want to prioritise
         // longer sequences.
-        seen_entries.sort_by(|(a_seq, a_count), (b_seq, b_count)| {
-            (b_count * b_seq.len()).cmp(&(a_count * a_seq.len()))
-        });
+        let heuristic = self.options.sort_heuristic;
+        seen_entries.sort_by_key(|&(seq, count)| Reverse(heuristic.key(seq, count)));
+
+        seen_entries.truncate(self.options.dict_size);
 
-        seen_entries.truncate(MAX_DICT_MULTI_BYTE_LEN);
+        let mut trie = Trie::new();
+        for (id, (seq, _)) in seen_entries.iter().enumerate() {
+            trie.insert(seq, id);
+        }
 
+        let (seen_entries, compressed) = match self.options.level {
+            CompressionLevel::Greedy => self.tokenize_greedy(seen_entries, trie),
+            CompressionLevel::Optimal => self.tokenize_optimal(seen_entries, trie),
+        };
+
+        let final_compressed = compressed
+            .into_iter()
+            .map(|record| {
+                record
+                    .into_iter()
+                    .map(|id| id.try_into().expect("Can't encode id as u32"))
+                    .collect()
+            })
+            .collect();
+
+        // Now to build the final dictionary.
+        let final_dict = seen_entries
+            .into_iter()
+            .zip(0..)
+            .map(|((seq, _), id)| (seq.to_vec(), id))
+            .collect();
+
+        (final_dict, final_compressed)
+    }
+
+    fn tokenize_greedy<'s>(
+        &'s self,
+        mut seen_entries: Vec<SeenEntry<'s>>,
+        mut trie: Trie,
+    ) -> (Vec<SeenEntry<'s>>, Vec<Vec<Id>>) {
         // Now we compress. We do this by looking for a prefix of the record in seen_entries,
-        // but we need to be aware that one may not exist. In that instance, just add the leading
-        // byte into the seen_sequences and carry on.
+        // taking the earliest one if there are several, but we need to be aware that one may not
+        // exist. In that instance, just add the leading byte into the seen_sequences and carry on.
         let mut final_compressed = Vec::new();
         for mut record in self.records.iter().map(Vec::as_slice) {
             let mut cur_compressed = Vec::new();
 
             while !record.is_empty() {
-                let id = if let Some((id, (prefix, _))) = seen_entries
-                    .iter()
-                    .enumerate()
-                    .find(|(_, (pf, _))| record.starts_with(pf))
-                {
+                let id = if let Some((id, prefix_len)) = trie.find_prefix(record) {
                     // Found prefix
-                    record = &record[prefix.len()..];
+                    record = &record[prefix_len..];
                     id
                 } else {
                     // No prefix found!
@@ -187,91 +541,364 @@ impl Compress {
                     record = rem;
                     let new_id = seen_entries.len();
                     seen_entries.push((std::slice::from_ref(first), 0));
+                    trie.insert(std::slice::from_ref(first), new_id);
                     new_id
                 };
 
-                cur_compressed.push(id.try_into().expect("Can't encode length as u16"));
+                cur_compressed.push(id);
             }
 
             final_compressed.push(cur_compressed);
         }
 
-        // Now to build the final dictionary.
-        let final_dict = seen_entries
-            .into_iter()
-            .zip(0..)
-            .map(|((seq, _), id)| (seq.to_vec(), id))
-            .collect();
+        (seen_entries, final_compressed)
+    }
 
-        (final_dict, final_compressed)
+    fn tokenize_optimal<'s>(
+        &'s self,
+        mut seen_entries: Vec<SeenEntry<'s>>,
+        mut trie: Trie,
+    ) -> (Vec<SeenEntry<'s>>, Vec<Vec<Id>>) {
+        // Every byte needs an entry, so that there's always at least one way through a record.
+        for record in &self.records {
+            for byte in record.chunks(1) {
+                if trie.find_prefix(byte).is_none() {
+                    trie.insert(byte, seen_entries.len());
+                    seen_entries.push((byte, 0));
+                }
+            }
+        }
+
+        let mut final_compressed = Vec::with_capacity(self.records.len());
+        let mut cost = Vec::new();
+        let mut step = Vec::new();
+        for record in &self.records {
+            let compressed = trie
+                .split_optimal(record, &mut cost, &mut step)
+                .expect("Every byte has an entry");
+            final_compressed.push(compressed);
+        }
+
+        // Ids were only a guess at what each entry would cost. Now that we know how often each
+        // one is used, drop the unused ones and give the shortest ids to the most used.
+        let mut uses = vec![0_usize; seen_entries.len()];
+        for id in final_compressed.iter().flatten() {
+            uses[*id] += 1;
+        }
+
+        let mut by_uses: Vec<Id> = (0..seen_entries.len()).filter(|&id| uses[id] > 0).collect();
+        by_uses.sort_by(|a, b| uses[*b].cmp(&uses[*a]));
+
+        let mut new_ids = vec![0; seen_entries.len()];
+        for (new_id, &id) in by_uses.iter().enumerate() {
+            new_ids[id] = new_id;
+        }
+
+        for id in final_compressed.iter_mut().flatten() {
+            *id = new_ids[*id];
+        }
+        let seen_entries = by_uses.into_iter().map(|id| seen_entries[id]).collect();
+
+        (seen_entries, final_compressed)
     }
 
     /// Stores the compressed archive into a data structure readable by `Decompress`.
+    #[cfg(test)]
     #[must_use]
     pub fn store_archive(&self) -> Vec<u8> {
         let stage2_counts = self.apply_stage2();
         let (final_dict, compressed_records) = self.apply_stage3(stage2_counts);
+        Self::store_plain(&final_dict, &compressed_records)
+    }
 
-        let mut archive = Vec::new();
-        let records_len: u16 = compressed_records
-            .len()
-            .try_into()
-            .expect("Records length too long");
+    /// Same as `store_archive`, but with the ids in each record Huffman coded. Readable by
+    /// `Decompress::open_huffman`.
+    ///
+    /// Returns `None` if more ids are used than there are codes of at most
+    /// `HUFFMAN_MAX_CODE_LEN` bits.
+    #[cfg(test)]
+    #[must_use]
+    pub fn store_huffman_archive(&self) -> Option<Vec<u8>> {
+        let stage2_counts = self.apply_stage2();
+        let (final_dict, compressed_records) = self.apply_stage3(stage2_counts);
+        Self::store_huffman(&final_dict, &compressed_records)
+    }
 
-        let dictionary_keys: BTreeMap<_, _> = final_dict.iter().map(|(k, v)| (v, k)).collect();
-        let lookup_length_bytes = dictionary_keys.len() * ADDR_SIZE;
-        archive.resize(lookup_length_bytes + LOOKUP_START, 0);
+    /// Returns the results of both `store_archive` and `store_huffman_archive`, only
+    /// compressing the records once. There's no Huffman coded archive if the options don't
+    /// allow it.
+    #[must_use]
+    pub fn store_archives(&self) -> (Vec<u8>, Option<Vec<u8>>) {
+        let stage2_counts = self.apply_stage2();
+        let (final_dict, compressed_records) = self.apply_stage3(stage2_counts);
+        (
+            Self::store_plain(&final_dict, &compressed_records),
+            self.store_huffman_if_allowed(&final_dict, &compressed_records),
+        )
+    }
+
+    /// Compresses the records against `dict` rather than a dictionary of their own, readable by
+    /// `Decompress::open_with_dict`. Records are always split as `CompressionLevel::Optimal`
+    /// would, as the ids are fixed.
+    ///
+    /// Returns `None` if the records hold bytes which aren't in `dict`.
+    #[must_use]
+    pub fn store_shared_archive(&self, dict: &SharedDictionary) -> Option<Vec<u8>> {
+        let compressed_records = self.tokenize_fixed(&dict.entries)?;
+
+        let mut archive = dict.id().to_le_bytes().to_vec();
+        archive.extend_from_slice(Leb128::encode_usize(compressed_records.len()).as_ref());
+        store_records(&mut archive, &compressed_records);
+        Some(archive)
+    }
 
-        // Number of records.
-        archive[NUM_RECORD_ADDR].copy_from_slice(records_len.to_le_bytes().as_ref());
+    /// Same as `store_archives`, but compresses the records against `entries` instead of
+    /// building a dictionary for them, and stores `entries` as the dictionary. Records are split
+    /// as `CompressionLevel::Optimal` would.
+    ///
+    /// Returns `None` if the records hold bytes which aren't in `entries`, or if an entry appears
+    /// more than once.
+    #[must_use]
+    pub fn store_archives_with_dict(
+        &self,
+        entries: &[Vec<u8>],
+    ) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        let final_dict: BTreeMap<_, _> = entries.iter().cloned().zip(0..).collect();
+        if final_dict.len() != entries.len() {
+            return None;
+        }
 
-        let mut cur_addr: u16 = 0; // Address relative to dictionary start.
-                                   // Update the dictionary start address.
-        let dict_start_addr: u16 = archive.len().try_into().expect("Archive length too long");
-        archive[DICT_START_ADDR].copy_from_slice(dict_start_addr.to_le_bytes().as_ref());
+        let compressed_records = self.tokenize_fixed(entries)?;
+        Some((
+            Self::store_plain(&final_dict, &compressed_records),
+            self.store_huffman_if_allowed(&final_dict, &compressed_records),
+        ))
+    }
 
-        for (idx, val) in &dictionary_keys {
-            let idx = **idx as usize;
+    fn store_huffman_if_allowed(
+        &self,
+        final_dict: &BTreeMap<Vec<u8>, SmallId>,
+        compressed_records: &[Vec<SmallId>],
+    ) -> Option<Vec<u8>> {
+        if !self.options.allow_huffman {
+            return None;
+        }
+        Self::store_huffman(final_dict, compressed_records)
+    }
 
-            // Update the dictionary lookup with cur_addr.
-            archive[LOOKUP_START + idx * ADDR_SIZE..][..ADDR_SIZE]
-                .copy_from_slice(cur_addr.to_le_bytes().as_ref());
+    // Splits each record into the ids of `entries`, numbered in the order given. Returns `None`
+    // if part of a record isn't covered by any entry.
+    fn tokenize_fixed(&self, entries: &[Vec<u8>]) -> Option<Vec<Vec<SmallId>>> {
+        let mut trie = Trie::new();
+        for (id, entry) in entries.iter().enumerate() {
+            trie.insert(entry, id);
+        }
 
-            // Write the length of dictionary entry (u16)
-            let entry_len: u16 = val
-                .len()
-                .try_into()
-                .expect("Dictionary entry length too long");
-            archive.write_all(entry_len.to_le_bytes().as_ref()).unwrap();
+        let mut cost = Vec::new();
+        let mut step = Vec::new();
+        self.records
+            .iter()
+            .map(|record| {
+                let ids = trie.split_optimal(record, &mut cost, &mut step)?;
+                Some(ids.into_iter().map(|id| id as SmallId).collect())
+            })
+            .collect()
+    }
 
-            // Write the dictionary contents.
-            archive.write_all(&val).unwrap();
+    fn store_plain(
+        final_dict: &BTreeMap<Vec<u8>, SmallId>,
+        compressed_records: &[Vec<SmallId>],
+    ) -> Vec<u8> {
+        let dictionary_keys: BTreeMap<_, _> = final_dict.iter().map(|(k, v)| (v, k)).collect();
+        let mut archive = Leb128::encode_usize(compressed_records.len())
+            .as_ref()
+            .to_vec();
+        archive.extend_from_slice(&store_dictionary(
+            dictionary_keys.values().map(|val| val.as_slice()),
+        ));
+
+        store_records(&mut archive, compressed_records);
+        archive
+    }
 
-            // Update cur_addr to start of new entry.
-            cur_addr += ADDR_SIZE as u16 + entry_len;
+    fn store_huffman(
+        final_dict: &BTreeMap<Vec<u8>, SmallId>,
+        compressed_records: &[Vec<SmallId>],
+    ) -> Option<Vec<u8>> {
+        let mut uses = vec![0; final_dict.len()];
+        for &id in compressed_records.iter().flatten() {
+            uses[id as usize] += 1;
+        }
+        let code_lens = huffman_code_lens(&uses)?;
+
+        // Canonical codes are handed out in order of length, so if the entries are numbered in
+        // that order too the decoder only needs to know how many codes there are of each length.
+        // Unused entries are dropped.
+        let mut order: Vec<usize> = (0..uses.len()).filter(|&id| uses[id] > 0).collect();
+        order.sort_by_key(|&id| (code_lens[id], id));
+
+        let mut codes = vec![(0_u32, 0); uses.len()];
+        let mut code = 0_u32;
+        let mut prev_len = order.first().map_or(0, |&id| code_lens[id]);
+        for &id in &order {
+            code <<= code_lens[id] - prev_len;
+            codes[id] = (code, code_lens[id]);
+            code += 1;
+            prev_len = code_lens[id];
         }
 
-        // Update the record start address.
-        let record_start_addr: u16 = archive.len().try_into().expect("Record length too long");
-        archive[RECORD_START_ADDR].copy_from_slice(record_start_addr.to_le_bytes().as_ref());
+        let dictionary_keys: BTreeMap<_, _> = final_dict.iter().map(|(k, v)| (v, k)).collect();
+        let entries: Vec<&[u8]> = dictionary_keys.values().map(|val| val.as_slice()).collect();
+        let mut archive = Leb128::encode_usize(compressed_records.len())
+            .as_ref()
+            .to_vec();
+        archive.extend_from_slice(&store_dictionary(order.iter().map(|&id| entries[id])));
+
+        // The code length table is the longest length, then the number of codes of each length.
+        let max_len = code_lens.iter().copied().max().unwrap_or(0);
+        archive.push(max_len as u8);
+        for len in 1..=max_len {
+            let count = code_lens.iter().filter(|&&l| l == len).count();
+            archive.extend_from_slice(Leb128::encode_usize(count).as_ref());
+        }
 
         for record in compressed_records {
-            // Write the length of the record.
-            let cur_record_len = record.len().try_into().expect("Record len too big");
+            archive.extend_from_slice(Leb128::encode_usize(record.len()).as_ref());
+
+            // Codes are packed most significant bit first, with the last byte padded out.
+            let mut bits = 0_u8;
+            let mut num_bits = 0;
+            for &id in record {
+                let (code, len) = codes[id as usize];
+                for bit in (0..len).rev() {
+                    bits = bits << 1 | ((code >> bit) & 1) as u8;
+                    num_bits += 1;
+                    if num_bits == 8 {
+                        archive.push(bits);
+                        bits = 0;
+                        num_bits = 0;
+                    }
+                }
+            }
+            if num_bits > 0 {
+                archive.push(bits << (8 - num_bits));
+            }
+        }
+
+        Some(archive)
+    }
+}
+
+// Writes each record as its length, followed by its LEB128 ids.
+fn store_records(archive: &mut Vec<u8>, compressed_records: &[Vec<SmallId>]) {
+    for record in compressed_records {
+        archive
+            .write_all(Leb128::encode_usize(record.len()).as_ref())
+            .unwrap();
+
+        for &val in record {
             archive
-                .write_all(RunLengthEncoded::encode(cur_record_len).as_ref())
+                .write_all(Leb128::encode(val.into()).as_ref())
                 .unwrap();
+        }
+    }
+}
 
-            // Write the record.
-            for val in record {
-                archive
-                    .write_all(RunLengthEncoded::encode(val).as_ref())
-                    .unwrap();
-            }
+// Writes the dictionary header, lookup table and dictionary, with the entries numbered in the
+// order given.
+fn store_dictionary<'e>(entries: impl Iterator<Item = &'e [u8]>) -> Vec<u8> {
+    // We need to know how big the dictionary is before we know how wide the lookup table
+    // addresses need to be, so the dictionary is built first.
+    let mut dict = Vec::new();
+    let mut lookup_addrs = Vec::new();
+    for val in entries {
+        lookup_addrs.push(dict.len());
+
+        // Write the length of dictionary entry, followed by the contents.
+        dict.write_all(Leb128::encode_usize(val.len()).as_ref())
+            .unwrap();
+        dict.write_all(val).unwrap();
+    }
+
+    let width = addr_width(lookup_addrs.last().copied().unwrap_or(0));
+
+    let mut archive = Vec::new();
+    archive
+        .write_all(Leb128::encode_usize(lookup_addrs.len()).as_ref())
+        .unwrap();
+    #[allow(clippy::cast_possible_truncation)]
+    archive.write_all(&[width as u8]).unwrap();
+    archive
+        .write_all(Leb128::encode_usize(dict.len()).as_ref())
+        .unwrap();
+
+    for addr in lookup_addrs {
+        write_addr(&mut archive, addr, width);
+    }
+
+    archive.write_all(&dict).unwrap();
+    archive
+}
+
+// Finds the length of the Huffman code for each symbol, given how many times each is used.
+// Unused symbols get a length of 0, and no code is longer than `HUFFMAN_MAX_CODE_LEN`.
+//
+// Returns `None` if more symbols are used than there are codes that short.
+fn huffman_code_lens(uses: &[usize]) -> Option<Vec<usize>> {
+    if uses.iter().filter(|&&n| n > 0).count() > 1 << HUFFMAN_MAX_CODE_LEN {
+        return None;
+    }
+
+    let mut weights = uses.to_vec();
+    loop {
+        let code_lens = unlimited_code_lens(&weights);
+        if code_lens.iter().all(|&len| len <= HUFFMAN_MAX_CODE_LEN) {
+            return Some(code_lens);
         }
 
-        archive
+        // Flatten the weights until the tree is shallow enough. At worst they all end up as 1,
+        // which gives a balanced tree.
+        for weight in weights.iter_mut().filter(|w| **w > 0) {
+            *weight = weight.div_ceil(2);
+        }
+    }
+}
+
+fn unlimited_code_lens(weights: &[usize]) -> Vec<usize> {
+    let symbols: Vec<usize> = (0..weights.len()).filter(|&s| weights[s] > 0).collect();
+    let mut code_lens = vec![0; weights.len()];
+    if let [symbol] = symbols[..] {
+        // A code still needs at least one bit.
+        code_lens[symbol] = 1;
+        return code_lens;
+    }
+
+    // Leaves are nodes `0..symbols.len()`, with the nodes joining them after.
+    let mut parents = vec![usize::MAX; symbols.len()];
+    let mut heap: BinaryHeap<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, &s)| Reverse((weights[s], node)))
+        .collect();
+    while let (Some(Reverse((a_weight, a))), Some(Reverse((b_weight, b)))) =
+        (heap.pop(), heap.pop())
+    {
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((a_weight + b_weight, node)));
     }
+
+    for (leaf, &s) in symbols.iter().enumerate() {
+        let mut node = leaf;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            code_lens[s] += 1;
+        }
+    }
+
+    code_lens
 }
 
 #[cfg(test)]
@@ -337,7 +964,7 @@ mod tests {
         .iter()
         .cloned()
         .collect();
-        let expected_compressed: Vec<Vec<u16>> = vec![vec![0, 1, 2, 3, 4, 5]];
+        let expected_compressed: Vec<Vec<u32>> = vec![vec![0, 1, 2, 3, 4, 5]];
 
         assert_eq!(expected_dict, stage3_dict);
         assert_eq!(expected_compressed, compressed);
@@ -356,7 +983,7 @@ mod tests {
 
         let mut expected_dict = BTreeMap::new();
         expected_dict.insert(input.as_bytes().to_vec(), 0);
-        let expected_compressed: Vec<Vec<u16>> = vec![vec![0]];
+        let expected_compressed: Vec<Vec<u32>> = vec![vec![0]];
 
         assert_eq!(expected_dict, stage3_dict);
         assert_eq!(expected_compressed, compressed);
@@ -367,9 +994,8 @@ mod tests {
         let mut archive = Compress::new();
         archive.add_record("TOBEORNOTTOBEORTOBEORNOT");
         let stage2_counts = archive.apply_stage2();
-        let (stage3_dict, records) = archive.apply_stage3(stage2_counts);
+        let (_, records) = archive.apply_stage3(stage2_counts);
 
-        println!("{:#?}", stage3_dict);
         let expected = vec![vec![0, 1, 2, 5, 4, 3, 0, 1, 2, 6]];
 
         assert_eq!(records, expected);
@@ -388,6 +1014,144 @@ mod tests {
         assert_eq!(records, expected);
     }
 
+    #[test]
+    fn trie_prefers_earliest_entry() {
+        let mut trie = Trie::new();
+        trie.insert(b"ab", 0);
+        trie.insert(b"abcd", 2);
+        trie.insert(b"a", 1);
+        trie.insert(b"x", 3);
+
+        assert_eq!(trie.find_prefix(b"abcde"), Some((0, 2)));
+        assert_eq!(trie.find_prefix(b"abc"), Some((0, 2)));
+        assert_eq!(trie.find_prefix(b"ax"), Some((1, 1)));
+        assert_eq!(trie.find_prefix(b"xa"), Some((3, 1)));
+        assert_eq!(trie.find_prefix(b"b"), None);
+        assert_eq!(trie.find_prefix(b""), None);
+    }
+
+    #[test]
+    fn compress_optimal() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+
+        let mut greedy = Compress::new();
+        let mut optimal =
+            Compress::with_options(CompressOptions::new().level(CompressionLevel::Optimal));
+        for line in input_text.lines() {
+            greedy.add_record(line);
+            optimal.add_record(line);
+        }
+
+        let greedy_output = greedy.store_archive();
+        let output = optimal.store_archive();
+        assert!(output.len() < greedy_output.len());
+
+        let mut reader = crate::decompress::Decompress::open(&output, crate::Layout::Wide).unwrap();
+        let mut buf = [0_u8; 512];
+        for (i, line) in input_text.lines().enumerate() {
+            let record = reader.next_record(&mut buf).unwrap();
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn options() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+
+        for &heuristic in &[
+            SortHeuristic::Savings,
+            SortHeuristic::Count,
+            SortHeuristic::Length,
+        ] {
+            let options = CompressOptions::new()
+                .dict_size(20)
+                .max_entry_len(4)
+                .sort_heuristic(heuristic);
+            let mut archive = Compress::with_options(options);
+            for line in input_text.lines() {
+                archive.add_record(line);
+            }
+
+            assert!(archive.dict.keys().all(|seq| seq.len() <= 4));
+
+            let stage2_counts = archive.apply_stage2();
+            let (stage3_dict, _) = archive.apply_stage3(stage2_counts);
+            let multi_byte = stage3_dict.keys().filter(|seq| seq.len() > 1).count();
+            assert!(multi_byte <= 20, "{:?}: {}", heuristic, multi_byte);
+
+            let output = archive.store_archive();
+            let mut reader =
+                crate::decompress::Decompress::open(&output, crate::Layout::Wide).unwrap();
+            let mut buf = [0_u8; 512];
+            for (i, line) in input_text.lines().enumerate() {
+                let record = reader.next_record(&mut buf).unwrap();
+                assert_eq!(record, Some(line.as_bytes()), "{heuristic:?} {i}: {line}");
+            }
+        }
+    }
+
+    #[test]
+    fn huffman_code_lens_limited() {
+        // Fibonacci weights give the deepest possible tree.
+        let mut uses = vec![1, 1];
+        while uses.len() < 30 {
+            uses.push(uses[uses.len() - 1] + uses[uses.len() - 2]);
+        }
+        uses.push(0);
+
+        let code_lens = huffman_code_lens(&uses).unwrap();
+        assert_eq!(0, code_lens[30]);
+        assert!(code_lens[..30]
+            .iter()
+            .all(|&len| (1..=HUFFMAN_MAX_CODE_LEN).contains(&len)));
+
+        // The codes must fit in the code space.
+        let code_space: usize = code_lens[..30]
+            .iter()
+            .map(|&len| 1 << (HUFFMAN_MAX_CODE_LEN - len))
+            .sum();
+        assert!(code_space <= 1 << HUFFMAN_MAX_CODE_LEN);
+
+        assert_eq!(Some(vec![0, 1, 0]), huffman_code_lens(&[0, 5, 0]));
+        assert_eq!(Some(vec![1, 2, 2]), huffman_code_lens(&[4, 1, 1]));
+    }
+
+    #[test]
+    fn huffman_code_lens_too_many_symbols() {
+        // As many symbols as there are 15 bit codes still fit, with every code that long.
+        let mut uses = vec![2; 1 << HUFFMAN_MAX_CODE_LEN];
+        let code_lens = huffman_code_lens(&uses).unwrap();
+        assert!(code_lens.iter().all(|&len| len == HUFFMAN_MAX_CODE_LEN));
+
+        // Unused symbols don't need a code.
+        uses.push(0);
+        assert!(huffman_code_lens(&uses).is_some());
+
+        uses.push(1);
+        assert_eq!(huffman_code_lens(&uses), None);
+    }
+
+    #[test]
+    fn huffman_archive_round_trip() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let mut archive = Compress::new();
+        for line in input_text.lines() {
+            archive.add_record(line);
+        }
+
+        let output = archive.store_huffman_archive().unwrap();
+        assert!(output.len() < archive.store_archive().len());
+
+        let mut reader = crate::decompress::Decompress::open_huffman(&output).unwrap();
+        let mut buf = [0_u8; 512];
+        for (i, line) in input_text.lines().enumerate() {
+            let record = reader.next_record(&mut buf).unwrap();
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+    }
+
     #[test]
     fn archive_round_trip() {
         let input_text = "Hello World!";
@@ -398,7 +1162,7 @@ mod tests {
 
         let output = archive.store_archive();
 
-        let mut reader = crate::decompress::Decompress::open(&output);
+        let mut reader = crate::decompress::Decompress::open(&output, crate::Layout::Wide).unwrap();
         assert_eq!(reader.num_records(), 2);
 
         let mut buf = [0_u8; 50];
@@ -408,6 +1172,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn archives_with_dict() {
+        let mut archive = Compress::new();
+        archive.add_record("Hello World!");
+        archive.add_record("World Hello");
+
+        let entries: Vec<Vec<u8>> = ["Hello", "World", " ", "!", "x"]
+            .iter()
+            .map(|e| e.as_bytes().to_vec())
+            .collect();
+        let (plain, huffman) = archive.store_archives_with_dict(&entries).unwrap();
+        let huffman = huffman.unwrap();
+
+        let plain = crate::decompress::Decompress::open(&plain, crate::Layout::Wide).unwrap();
+        let huffman = crate::decompress::Decompress::open_huffman(&huffman).unwrap();
+        let plain_entries: Vec<_> = plain.dict_entries().map(Result::unwrap).collect();
+        assert_eq!(plain_entries, entries);
+        // Huffman coded archives drop the entries that aren't used.
+        assert_eq!(huffman.num_dict_entries(), 4);
+
+        for mut reader in [plain, huffman] {
+            let mut buf = [0_u8; 50];
+            assert_eq!(
+                reader.next_record(&mut buf).unwrap(),
+                Some(&b"Hello World!"[..])
+            );
+            assert_eq!(
+                reader.next_record(&mut buf).unwrap(),
+                Some(&b"World Hello"[..])
+            );
+        }
+
+        let duplicated = [entries.clone(), entries.clone()].concat();
+        assert!(archive.store_archives_with_dict(&duplicated).is_none());
+
+        // There's no entry with an "r" on its own.
+        archive.replace_record(1, "r");
+        assert!(archive.store_archives_with_dict(&entries).is_none());
+    }
+
+    #[test]
+    fn shared_dictionary() {
+        let corpus = ["R75,D30,R83,U83,L12", "U62,R66,U55,R34,D71", "R8,U5,L5,D3"];
+        let dict =
+            SharedDictionary::train(CompressOptions::new(), corpus.iter().map(|r| r.as_bytes()));
+
+        // Only what the corpus holds.
+        assert!(dict.num_entries() > 0);
+        for entry in &dict.entries {
+            assert!(corpus
+                .iter()
+                .any(|r| r.as_bytes().windows(entry.len()).any(|w| w == &entry[..])));
+        }
+
+        let stored = dict.store();
+        assert_eq!(stored[..4], DICT_MAGIC);
+        assert_eq!(stored[stored.len() - 4..], dict.id().to_le_bytes());
+        assert_eq!(SharedDictionary::load(&stored).unwrap(), dict);
+        assert!(SharedDictionary::load(&stored[..stored.len() - 1]).is_err());
+
+        let mut archive = Compress::new();
+        archive.add_record("U7,R6,D4,L4");
+        archive.add_record("R75,D30");
+        let output = archive.store_shared_archive(&dict).unwrap();
+
+        let mut reader = crate::decompress::Decompress::open_with_dict(&output, &stored).unwrap();
+        assert_eq!(reader.num_records(), 2);
+
+        let mut buf = [0_u8; 50];
+        assert_eq!(
+            reader.next_record(&mut buf).unwrap(),
+            Some(&b"U7,R6,D4,L4"[..])
+        );
+        assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&b"R75,D30"[..]));
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+
+        // There's no 'X' in the corpus.
+        archive.add_record("X");
+        assert!(archive.store_shared_archive(&dict).is_none());
+    }
+
     #[test]
     fn big_file_test() {
         let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
@@ -421,13 +1266,13 @@ mod tests {
 
         let output = archive.store_archive();
 
-        let mut reader = crate::decompress::Decompress::open(&output);
+        let mut reader = crate::decompress::Decompress::open(&output, crate::Layout::Wide).unwrap();
         assert_eq!(num_records, reader.num_records());
 
         let mut buf = [0_u8; 50];
         for (i, line) in input_text.lines().enumerate() {
             let record = reader.next_record(&mut buf).unwrap();
-            assert_eq!(record, Some(line.as_bytes()), "{}: {}", i, line);
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
         }
     }
 }
@@ -0,0 +1,52 @@
+// CRC-32 (IEEE 802.3), the same variant used by zlib and PNG.
+// The table is built at compile time, so it lives in flash rather than RAM.
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut i = 0;
+    while i < table.len() {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut crc = i as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+#[must_use]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+}
@@ -1,12 +1,23 @@
-use crate::{ErrorKind, RunLengthEncoded, RECORD_START_ADDR};
+use core::convert::TryFrom;
+
+use crate::{
+    crc::crc32, read_addr, ErrorKind, Layout, Leb128, CHECKSUM_LEN, DICT_MAGIC,
+    HUFFMAN_MAX_CODE_LEN, RECORD_START_ADDR,
+};
 
 use super::{ADDR_SIZE, DICT_START_ADDR, LOOKUP_START, NUM_RECORD_ADDR};
 
-#[derive(Debug)]
+// Lookup table addresses wider than this can't be indexed on a 32-bit target.
+const MAX_ADDR_WIDTH: usize = core::mem::size_of::<usize>();
+
+#[derive(Debug, Clone)]
 pub struct Decompress<'a> {
     // Stores the index into the dictionary table.
     dict_lookup: &'a [u8],
 
+    // Width in bytes of each address in `dict_lookup`.
+    addr_width: usize,
+
     //  Decompression dictionary.
     dict: &'a [u8],
 
@@ -17,34 +28,282 @@ pub struct Decompress<'a> {
 
     // Stores the index into the `records` field above for the start of the next record.
     current_record: usize,
+
+    layout: Layout,
+
+    // Only present if the ids in each record are Huffman coded.
+    huffman: Option<HuffmanTable>,
+}
+
+// Canonical Huffman codes for the dictionary ids. The ids are numbered in code order, so all the
+// decoder needs is the number of codes of each length, from which it builds a table of where the
+// codes of each length start.
+#[derive(Debug, Clone, Copy)]
+struct HuffmanTable {
+    // For each code length, one past its last code, padded with zeros to `max_len` bits. A code
+    // has the first length whose limit is above the next `max_len` bits.
+    limits: [u16; HUFFMAN_MAX_CODE_LEN],
+    // For each code length, its first code and the id that stands for. Codes are at most
+    // `HUFFMAN_MAX_CODE_LEN` bits, so there are no more ids than fit in those bits either.
+    first_codes: [u16; HUFFMAN_MAX_CODE_LEN],
+    first_ids: [u16; HUFFMAN_MAX_CODE_LEN],
+    max_len: usize,
+    // Bytes the table takes up in the archive.
+    stored_len: usize,
+}
+
+// The table entries only go up to `1 << HUFFMAN_MAX_CODE_LEN`.
+const _: () = assert!(HUFFMAN_MAX_CODE_LEN < 16);
+
+impl HuffmanTable {
+    // The table is the longest code length, then the number of codes of each length.
+    fn open(data: &[u8]) -> Result<(Self, &[u8]), ErrorKind> {
+        let (&max_len, mut rest) = data.split_first().ok_or(ErrorKind::HeaderOutOfRange)?;
+        let max_len = max_len as usize;
+        if max_len > HUFFMAN_MAX_CODE_LEN {
+            return Err(ErrorKind::HeaderOutOfRange);
+        }
+
+        let entry = |val: usize| u16::try_from(val).map_err(|_| ErrorKind::HeaderOutOfRange);
+        let mut limits = [0; HUFFMAN_MAX_CODE_LEN];
+        let mut first_codes = [0; HUFFMAN_MAX_CODE_LEN];
+        let mut first_ids = [0; HUFFMAN_MAX_CODE_LEN];
+        let mut code = 0_usize;
+        let mut id = 0_usize;
+        for len in 1..=max_len {
+            let (count, rem) = Leb128::decode_usize(rest).ok_or(ErrorKind::HeaderOutOfRange)?;
+            rest = rem;
+
+            // The counts come from the archive, so they may be nonsense. There can't be more
+            // codes than fit in `len` bits.
+            let end = code.saturating_add(count).min(1 << len);
+            limits[len - 1] = entry(end << (max_len - len))?;
+            first_codes[len - 1] = entry(code)?;
+            first_ids[len - 1] = entry(id)?;
+
+            id += end - code;
+            code = end << 1;
+        }
+
+        let stored_len = data.len() - rest.len();
+        Ok((
+            Self {
+                limits,
+                first_codes,
+                first_ids,
+                max_len,
+                stored_len,
+            },
+            rest,
+        ))
+    }
+
+    // Reads one code and returns the id it stands for.
+    fn decode(&self, bits: &mut BitReader<'_>) -> Option<usize> {
+        let next = bits.peek(self.max_len);
+        let i = self.limits[..self.max_len]
+            .iter()
+            .position(|&limit| next < usize::from(limit))?;
+
+        let len = i + 1;
+        let code = next >> (self.max_len - len);
+        bits.consume(len)?;
+        Some(usize::from(self.first_ids[i]) + code - usize::from(self.first_codes[i]))
+    }
+}
+
+// Reads bits most significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+// `BitReader::peek` reads the 3 bytes holding the next bits.
+const _: () = assert!(HUFFMAN_MAX_CODE_LEN + 7 <= 24);
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, bit: 0 }
+    }
+
+    // Returns the next `n` bits without moving past them, padded with zeros past the end of
+    // the data. `n` is at most `HUFFMAN_MAX_CODE_LEN`.
+    fn peek(&self, n: usize) -> usize {
+        let start = self.bit / 8;
+        let bytes = (start..start + 3).fold(0_usize, |bytes, i| {
+            bytes << 8 | usize::from(self.data.get(i).copied().unwrap_or(0))
+        });
+        (bytes << (self.bit % 8) & 0xFF_FFFF) >> (24 - n)
+    }
+
+    // Moves past the next `n` bits, or returns `None` if there aren't that many left.
+    fn consume(&mut self, n: usize) -> Option<()> {
+        let bit = self.bit + n;
+        if bit > self.data.len() * 8 {
+            return None;
+        }
+
+        self.bit = bit;
+        Some(())
+    }
+
+    // Includes the partly read byte, if any.
+    fn bytes_read(&self) -> usize {
+        self.bit.div_ceil(8)
+    }
 }
 
 impl<'a> Decompress<'a> {
-    pub fn open<T: AsRef<[u8]> + ?Sized + 'a>(data: &'a T) -> Self {
+    /// # Errors
+    ///
+    /// Returns an error if the header is truncated, or if the lookup table, dictionary or records
+    /// the header points to lie outside of `data`.
+    pub(crate) fn open<T: AsRef<[u8]> + ?Sized + 'a>(
+        data: &'a T,
+        layout: Layout,
+    ) -> Result<Self, ErrorKind> {
         let data = data.as_ref();
 
-        let mut size_buf = [0_u8; 2];
-
-        size_buf.copy_from_slice(&data[NUM_RECORD_ADDR]);
-        let num_records = u16::from_le_bytes(size_buf) as usize;
+        match layout {
+            Layout::Narrow => Self::open_narrow(data),
+            Layout::Wide => Self::open_wide(data),
+        }
+    }
 
-        size_buf.copy_from_slice(&data[DICT_START_ADDR]);
-        let dict_start_addr = u16::from_le_bytes(size_buf) as usize;
+    fn open_narrow(data: &'a [u8]) -> Result<Self, ErrorKind> {
+        let read_header_addr =
+            |addr| read_addr(data, addr, ADDR_SIZE).ok_or(ErrorKind::HeaderOutOfRange);
 
-        size_buf.copy_from_slice(&data[RECORD_START_ADDR]);
-        let record_idx_addr = u16::from_le_bytes(size_buf) as usize;
+        let num_records = read_header_addr(NUM_RECORD_ADDR.start)?;
+        let dict_start_addr = read_header_addr(DICT_START_ADDR.start)?;
+        let record_idx_addr = read_header_addr(RECORD_START_ADDR.start)?;
 
-        let dict_lookup = &data[LOOKUP_START..dict_start_addr];
-        let dict = &data[dict_start_addr..record_idx_addr];
-        let records = &data[record_idx_addr..];
+        let dict_lookup = data
+            .get(LOOKUP_START..dict_start_addr)
+            .ok_or(ErrorKind::LookupOutOfRange)?;
+        let dict = data
+            .get(dict_start_addr..record_idx_addr)
+            .ok_or(ErrorKind::DictionaryOutOfRange)?;
+        let records = data
+            .get(record_idx_addr..)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
 
-        Self {
+        Ok(Self {
             dict_lookup,
+            addr_width: ADDR_SIZE,
             dict,
             records,
             num_records,
             current_record: 0,
+            layout: Layout::Narrow,
+            huffman: None,
+        })
+    }
+
+    fn open_wide(data: &'a [u8]) -> Result<Self, ErrorKind> {
+        // The header is the number of records, followed by the dictionary. The records follow on
+        // directly after.
+        let (num_records, rest) = Leb128::decode_usize(data).ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (mut decompress, records) = Self::open_dictionary(rest)?;
+        decompress.num_records = num_records;
+        decompress.records = records;
+        Ok(decompress)
+    }
+
+    // Opens the dictionary at the start of `data`, returning a `Decompress` with no records and
+    // whatever follows the dictionary.
+    //
+    // The dictionary is the number of lookup entries, width of each lookup entry, and dictionary
+    // length, with the lookup table and dictionary following on directly after.
+    fn open_dictionary(data: &'a [u8]) -> Result<(Self, &'a [u8]), ErrorKind> {
+        let (lookup_len, rest) = Leb128::decode_usize(data).ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (&addr_width, rest) = rest.split_first().ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (dict_len, rest) = Leb128::decode_usize(rest).ok_or(ErrorKind::HeaderOutOfRange)?;
+
+        let addr_width = addr_width as usize;
+        if addr_width == 0 || addr_width > MAX_ADDR_WIDTH {
+            return Err(ErrorKind::HeaderOutOfRange);
         }
+
+        let lookup_len = lookup_len
+            .checked_mul(addr_width)
+            .ok_or(ErrorKind::LookupOutOfRange)?;
+        let dict_lookup = rest.get(..lookup_len).ok_or(ErrorKind::LookupOutOfRange)?;
+        let rest = &rest[lookup_len..];
+
+        let dict = rest
+            .get(..dict_len)
+            .ok_or(ErrorKind::DictionaryOutOfRange)?;
+
+        let decompress = Self {
+            dict_lookup,
+            addr_width,
+            dict,
+            records: &[],
+            num_records: 0,
+            current_record: 0,
+            layout: Layout::Wide,
+            huffman: None,
+        };
+        Ok((decompress, &rest[dict_len..]))
+    }
+
+    /// Opens an archive compressed against `dict`, a dictionary stored apart from it by
+    /// `SharedDictionary::store`. Only the wide layout has these.
+    ///
+    /// The archive is the id of the dictionary it was compressed against, the number of records,
+    /// then the records.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dict` isn't a valid dictionary, if the archive was compressed against
+    /// a different one, or if the header is truncated.
+    pub(crate) fn open_with_dict(data: &'a [u8], dict: &'a [u8]) -> Result<Self, ErrorKind> {
+        let (mut decompress, id) = Self::open_shared_dictionary(dict)?;
+        let archive_id = data
+            .get(..CHECKSUM_LEN)
+            .ok_or(ErrorKind::HeaderOutOfRange)?;
+        if archive_id != id {
+            return Err(ErrorKind::DictionaryMismatch);
+        }
+
+        let (num_records, records) =
+            Leb128::decode_usize(&data[CHECKSUM_LEN..]).ok_or(ErrorKind::HeaderOutOfRange)?;
+        decompress.num_records = num_records;
+        decompress.records = records;
+        Ok(decompress)
+    }
+
+    /// Opens a dictionary stored by `SharedDictionary::store` without any records, also
+    /// returning its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dict` isn't a valid dictionary.
+    pub(crate) fn open_shared_dictionary(dict: &'a [u8]) -> Result<(Self, &'a [u8]), ErrorKind> {
+        let (id, dict) = split_dictionary(dict)?;
+        let (decompress, rest) = Self::open_dictionary(dict)?;
+        if !rest.is_empty() {
+            return Err(ErrorKind::DictionaryOutOfRange);
+        }
+
+        Ok((decompress, id))
+    }
+
+    /// Opens an archive whose ids are Huffman coded. Only the wide layout has these.
+    ///
+    /// The code length table follows on from the dictionary, and each record is the number of
+    /// ids followed by their codes, padded out to a whole byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `open` would, or if the code length table is truncated or invalid.
+    pub(crate) fn open_huffman(data: &'a [u8]) -> Result<Self, ErrorKind> {
+        let mut decompress = Self::open_wide(data)?;
+        let (table, records) = HuffmanTable::open(decompress.records)?;
+        decompress.huffman = Some(table);
+        decompress.records = records;
+        Ok(decompress)
     }
 
     #[must_use]
@@ -52,20 +311,99 @@ impl<'a> Decompress<'a> {
         self.num_records
     }
 
-    fn dict_lookup(&self, id: u16) -> &[u8] {
-        let idx = id as usize * ADDR_SIZE;
+    pub(crate) fn position(&self) -> usize {
+        self.current_record
+    }
+
+    pub(crate) fn set_position(&mut self, position: usize) {
+        self.current_record = position;
+    }
+
+    pub(crate) fn end_position(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Moves past the next record without decoding it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated.
+    pub(crate) fn skip_record(&mut self) -> Result<Option<()>, ErrorKind> {
+        Ok(self.count_tokens()?.map(|_| ()))
+    }
+
+    /// Same as `skip_record`, but returns the number of dictionary ids the record is made of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated.
+    pub(crate) fn count_tokens(&mut self) -> Result<Option<usize>, ErrorKind> {
+        let remaining_bytes = self
+            .records
+            .get(self.current_record..)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
+        if remaining_bytes.is_empty() {
+            return Ok(None);
+        }
+
+        let (len, remaining_bytes) = self
+            .layout
+            .decode(remaining_bytes)
+            .ok_or_else(|| self.truncated())?;
+        let remaining_bytes = self.read_ids(remaining_bytes, len, |_| Ok(()))?;
+
+        self.current_record = self.records.len() - remaining_bytes.len();
+        Ok(Some(len))
+    }
+
+    #[must_use]
+    pub(crate) fn num_dict_entries(&self) -> usize {
+        self.dict_lookup.len() / self.addr_width
+    }
+
+    #[must_use]
+    pub(crate) fn lookup_len(&self) -> usize {
+        self.dict_lookup.len()
+    }
+
+    #[must_use]
+    pub(crate) fn dict_len(&self) -> usize {
+        self.dict.len()
+    }
+
+    /// Length of the Huffman code length table, or 0 if the ids aren't Huffman coded.
+    #[must_use]
+    pub(crate) fn code_table_len(&self) -> usize {
+        self.huffman.map_or(0, |table| table.stored_len)
+    }
+
+    /// Every entry in the dictionary, in id order.
+    pub(crate) fn dict_entries(&self) -> impl Iterator<Item = Result<&'a [u8], ErrorKind>> + '_ {
+        (0..self.num_dict_entries()).map(move |id| self.dict_lookup(id))
+    }
 
+    fn dict_lookup(&self, id: usize) -> Result<&'a [u8], ErrorKind> {
         // Decode address into dict.
-        let mut buf = [0_u8; 2];
-        buf.copy_from_slice(&self.dict_lookup[idx..idx + 2]);
-        let addr = u16::from_le_bytes(buf) as usize;
+        let addr = id
+            .checked_mul(self.addr_width)
+            .and_then(|idx| read_addr(self.dict_lookup, idx, self.addr_width))
+            .ok_or(ErrorKind::LookupOutOfRange)?;
 
         // Decode length of dict entry.
-        buf.copy_from_slice(&self.dict[addr..addr + 2]);
-        let len = u16::from_le_bytes(buf) as usize;
+        let (len, start) = match self.layout {
+            Layout::Narrow => (read_addr(self.dict, addr, ADDR_SIZE), addr + ADDR_SIZE),
+            Layout::Wide => match self.dict.get(addr..).and_then(Leb128::decode_usize) {
+                Some((len, rest)) => (Some(len), self.dict.len() - rest.len()),
+                None => (None, addr),
+            },
+        };
+        let len = len.ok_or(ErrorKind::DictionaryOutOfRange)?;
 
         // Now we finally get the slice to return.
-        &self.dict[addr + 2..addr + 2 + len]
+        start
+            .checked_add(len)
+            .and_then(|end| self.dict.get(start..end))
+            .ok_or(ErrorKind::DictionaryOutOfRange)
     }
 
     /// Reads the next record from the file.
@@ -77,35 +415,364 @@ impl<'a> Decompress<'a> {
     /// * On failure to read record length.
     /// * Record length exceeds output buffer length.
     /// * Record length exceeds remaining file length.
+    /// * A dictionary id lies outside of the lookup table or dictionary.
     pub fn next_record<'b>(&mut self, dst: &'b mut [u8]) -> Result<Option<&'b [u8]>, ErrorKind> {
-        if self.current_record == self.records.len() {
+        let start = self.current_record;
+
+        // Once the record no longer fits, the rest of it is only decoded to find its length.
+        let mut end = 0;
+        let found = self.decode_record(|dict_entry| {
+            if let Some(dst) = dst.get_mut(end..end + dict_entry.len()) {
+                dst.copy_from_slice(dict_entry);
+            }
+            end += dict_entry.len();
+            Ok(())
+        })?;
+
+        if end > dst.len() {
+            self.current_record = start;
+            return Err(ErrorKind::BufferTooSmall {
+                record: 0,
+                needed: end,
+            });
+        }
+
+        Ok(found.map(move |()| &dst[..end]))
+    }
+
+    /// Reads the next record from the file, passing it to `f` one dictionary entry at a time.
+    /// This allows records of any length to be read without a buffer to hold them.
+    ///
+    /// Returns the total length of the record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * Record length exceeds remaining file length.
+    /// * A dictionary id lies outside of the lookup table or dictionary.
+    pub fn stream_record(&mut self, mut f: impl FnMut(&[u8])) -> Result<Option<usize>, ErrorKind> {
+        let mut len = 0;
+        let found = self.decode_record(|dict_entry| {
+            len += dict_entry.len();
+            f(dict_entry);
+            Ok(())
+        })?;
+
+        Ok(found.map(|()| len))
+    }
+
+    /// Length of the next record once decoded, without moving past it. Records only store how
+    /// many ids they're made of, so this adds up the length of each id's dictionary entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `stream_record` would.
+    pub(crate) fn record_len(&self) -> Result<Option<usize>, ErrorKind> {
+        let mut len = 0;
+        let found = self.clone().decode_record(|dict_entry| {
+            len += dict_entry.len();
+            Ok(())
+        })?;
+
+        Ok(found.map(|()| len))
+    }
+
+    // Passes each dictionary entry of the next record to `f`.
+    // We only move on to the next record if the whole record was decoded.
+    fn decode_record(
+        &mut self,
+        mut f: impl FnMut(&'a [u8]) -> Result<(), ErrorKind>,
+    ) -> Result<Option<()>, ErrorKind> {
+        let remaining_bytes = self
+            .records
+            .get(self.current_record..)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
+        if remaining_bytes.is_empty() {
             return Ok(None);
         }
 
-        let (len, mut remaining_bytes) =
-            RunLengthEncoded::decode(&self.records[self.current_record..])
-                .ok_or(ErrorKind::RecordReadError)?;
+        let (len, remaining_bytes) = self
+            .layout
+            .decode(remaining_bytes)
+            .ok_or_else(|| self.truncated())?;
+        let remaining_bytes = self.read_ids(remaining_bytes, len, |id| f(self.dict_lookup(id)?))?;
+
+        self.current_record = self.records.len() - remaining_bytes.len();
+
+        Ok(Some(()))
+    }
+
+    // Reads `len` ids from the start of `data`, passing each to `f`, and returns what follows
+    // them.
+    fn read_ids(
+        &self,
+        mut data: &'a [u8],
+        len: usize,
+        mut f: impl FnMut(usize) -> Result<(), ErrorKind>,
+    ) -> Result<&'a [u8], ErrorKind> {
+        if let Some(table) = &self.huffman {
+            let mut bits = BitReader::new(data);
+            for _ in 0..len {
+                f(table.decode(&mut bits).ok_or_else(|| self.truncated())?)?;
+            }
 
-        if dst.len() < len as usize {
-            return Err(ErrorKind::RecordReadError);
+            return Ok(&data[bits.bytes_read()..]);
         }
 
-        let mut end = 0;
         for _ in 0..len {
-            let (id, rem) =
-                RunLengthEncoded::decode(remaining_bytes).ok_or(ErrorKind::RecordReadError)?;
-            remaining_bytes = rem;
+            let (id, rem) = self.layout.decode(data).ok_or_else(|| self.truncated())?;
+            data = rem;
 
-            let dict_entry = self.dict_lookup(id);
+            f(id)?;
+        }
 
-            dst[end..end + dict_entry.len()].copy_from_slice(dict_entry);
-            end += dict_entry.len();
+        Ok(data)
+    }
+
+    // The next record runs past the end of the records.
+    fn truncated(&self) -> ErrorKind {
+        ErrorKind::RecordTruncated {
+            record: 0,
+            offset: self.current_record,
         }
+    }
+}
+
+// Checks the magic and checksum of a stored dictionary, returning its id and contents.
+fn split_dictionary(data: &[u8]) -> Result<(&[u8], &[u8]), ErrorKind> {
+    let contents = data
+        .strip_prefix(&DICT_MAGIC[..])
+        .ok_or(ErrorKind::InvalidMagic)?;
+    let (contents, id) = contents
+        .len()
+        .checked_sub(CHECKSUM_LEN)
+        .map(|len| contents.split_at(len))
+        .ok_or(ErrorKind::ChecksumMismatch)?;
+
+    if crc32(&data[..data.len() - CHECKSUM_LEN]).to_le_bytes() != id {
+        return Err(ErrorKind::ChecksumMismatch);
+    }
+
+    Ok((id, contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{Compress, CompressOptions, SharedDictionary};
 
-        let written_buf = &dst[..end];
-        let len_dif = self.records.len() - self.current_record - remaining_bytes.len();
-        self.current_record += len_dif;
+    // Compressed payload written by version 3, holding "TOBEORNOT" seven times, "TOBE", then
+    // "TOBEORNOT".
+    const NARROW_PAYLOAD: &[u8] = &[
+        10, 0, 27, 0, 9, 0, 0, 0, 11, 0, 9, 0, 84, 79, 66, 69, 79, 82, 78, 79, 84, 4, 0, 84, 79,
+        66, 69, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 0,
+    ];
 
-        Ok(Some(written_buf))
+    fn read_all(data: &[u8], layout: Layout) -> Result<(), ErrorKind> {
+        let mut reader = Decompress::open(data, layout)?;
+        let mut buf = [0_u8; 50];
+        while reader.next_record(&mut buf)?.is_some() {}
+        Ok(())
+    }
+
+    #[test]
+    fn narrow_layout() {
+        let mut reader = Decompress::open(NARROW_PAYLOAD, Layout::Narrow).unwrap();
+        assert_eq!(reader.num_records(), 9);
+
+        let mut buf = [0_u8; 9];
+        for i in 0..9 {
+            let expected: &[u8] = if i == 7 { b"TOBE" } else { b"TOBEORNOT" };
+            assert_eq!(reader.next_record(&mut buf).unwrap(), Some(expected));
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+
+        assert!(matches!(
+            Decompress::open(&NARROW_PAYLOAD[..LOOKUP_START - 1], Layout::Narrow),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // None of these should panic.
+        for len in 0..NARROW_PAYLOAD.len() {
+            let _ = read_all(&NARROW_PAYLOAD[..len], Layout::Narrow);
+        }
+    }
+
+    #[test]
+    fn truncated_archive() {
+        let mut archive = Compress::new();
+        archive.add_record("TOBEORNOTTOBEORTOBEORNOT");
+        archive.add_record("Hello World!");
+        let output = archive.store_archive();
+
+        assert!(read_all(&output, Layout::Wide).is_ok());
+        assert!(matches!(
+            Decompress::open(&output[..2], Layout::Wide),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // None of these should panic.
+        for len in 0..output.len() {
+            let _ = read_all(&output[..len], Layout::Wide);
+        }
+    }
+
+    #[test]
+    fn corrupt_archive() {
+        // No records, no lookup entries, 1-byte addresses and an empty dictionary.
+        assert!(read_all(&[0, 0, 1, 0], Layout::Wide).is_ok());
+
+        // Address width too large.
+        assert!(matches!(
+            Decompress::open(&[0, 0, 9, 0], Layout::Wide),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // Lookup table running past the end of the archive.
+        assert!(matches!(
+            Decompress::open(&[0, 10, 1, 0], Layout::Wide),
+            Err(ErrorKind::LookupOutOfRange)
+        ));
+
+        // Dictionary running past the end of the archive.
+        assert!(matches!(
+            Decompress::open(&[0, 0, 1, 10], Layout::Wide),
+            Err(ErrorKind::DictionaryOutOfRange)
+        ));
+
+        let mut archive = Compress::new();
+        archive.add_record("TOBEORNOTTOBEORTOBEORNOT");
+        let output = archive.store_archive();
+
+        // Every byte value in every position should fail cleanly, if it fails.
+        for idx in 0..output.len() {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[idx] = val;
+                let _ = read_all(&corrupt, Layout::Wide);
+            }
+        }
+    }
+
+    #[test]
+    fn record_len() {
+        // Few ids, each standing for a long dictionary entry.
+        let mut archive = Compress::with_options(CompressOptions::new().max_entry_len(32));
+        for _ in 0..4 {
+            archive.add_record("TOBEORNOTTOBEORNOTTOBEORNOT");
+        }
+        archive.add_record("TOBE");
+        let output = archive.store_archive();
+
+        let mut reader = Decompress::open(&output, Layout::Wide).unwrap();
+        let tokens = reader.clone().count_tokens().unwrap().unwrap();
+        assert!(tokens < 27);
+        assert_eq!(reader.record_len().unwrap(), Some(27));
+
+        // Enough room for every token, but not every byte.
+        let mut buf = [0_u8; 27];
+        assert!(matches!(
+            reader.next_record(&mut buf[..tokens]),
+            Err(ErrorKind::BufferTooSmall { needed: 27, .. })
+        ));
+        assert_eq!(
+            reader.next_record(&mut buf).unwrap(),
+            Some(&b"TOBEORNOTTOBEORNOTTOBEORNOT"[..])
+        );
+
+        for _ in 0..3 {
+            reader.skip_record().unwrap();
+        }
+        assert_eq!(reader.record_len().unwrap(), Some(4));
+        reader.skip_record().unwrap();
+        assert_eq!(reader.record_len().unwrap(), None);
+    }
+
+    fn read_all_huffman(data: &[u8]) -> Result<(), ErrorKind> {
+        let mut reader = Decompress::open_huffman(data)?;
+        let mut buf = [0_u8; 50];
+        while reader.next_record(&mut buf)?.is_some() {}
+        Ok(())
+    }
+
+    #[test]
+    fn huffman_archive() {
+        let records = ["TOBEORNOTTOBEORTOBEORNOT", "", "Hello World!", "TOBEORNOT"];
+        let mut archive = Compress::new();
+        for record in &records {
+            archive.add_record(record);
+        }
+        let output = archive.store_huffman_archive().unwrap();
+
+        let mut reader = Decompress::open_huffman(&output).unwrap();
+        let mut buf = [0_u8; 50];
+        for record in &records {
+            assert_eq!(
+                reader.next_record(&mut buf).unwrap(),
+                Some(record.as_bytes())
+            );
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+
+        reader.set_position(0);
+        for _ in &records {
+            assert_eq!(reader.skip_record().unwrap(), Some(()));
+        }
+        assert_eq!(reader.position(), reader.end_position());
+
+        // Code lengths longer than we support.
+        assert!(matches!(
+            Decompress::open_huffman(&[0, 0, 1, 0, 16]),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // None of these should panic.
+        for len in 0..output.len() {
+            let _ = read_all_huffman(&output[..len]);
+        }
+        for idx in 0..output.len() {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[idx] = val;
+                let _ = read_all_huffman(&corrupt);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_dictionary() {
+        let records = ["TOBEORNOTTOBEORTOBEORNOT", "", "TOBEORNOT"];
+        let dict =
+            SharedDictionary::train(CompressOptions::new(), records.iter().map(|r| r.as_bytes()));
+        let stored = dict.store();
+
+        let mut archive = Compress::new();
+        archive.add_record("NOTTOBE");
+        let output = archive.store_shared_archive(&dict).unwrap();
+
+        let read_all = |data: &[u8], dict: &[u8]| -> Result<(), ErrorKind> {
+            let mut reader = Decompress::open_with_dict(data, dict)?;
+            let mut buf = [0_u8; 50];
+            while reader.next_record(&mut buf)?.is_some() {}
+            Ok(())
+        };
+        assert!(read_all(&output, &stored).is_ok());
+
+        // None of these should panic.
+        for len in 0..output.len() {
+            let _ = read_all(&output[..len], &stored);
+        }
+        for len in 0..stored.len() {
+            let _ = read_all(&output, &stored[..len]);
+        }
+        for idx in 0..output.len() {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[idx] = val;
+                let _ = read_all(&corrupt, &stored);
+            }
+        }
     }
 }
@@ -0,0 +1,301 @@
+use core::ops::Range;
+
+use crate::{
+    input::{BlockLens, PayloadParts, COMPRESSED, HUFFMAN, LZSS, NUMERIC, RAW, SHARED_DICT},
+    ArchiveLimits, ErrorKind, Reader, RecordKind, CHECKSUM_LEN, FLAGS_ADDR, HEADER_LEN, MAGIC_ADDR,
+    VERSION_ADDR,
+};
+
+/// How the records in an archive are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    Raw,
+    /// Dictionary ids, stored as LEB128.
+    Compressed,
+    Lzss,
+    /// Dictionary ids, Huffman coded.
+    Huffman,
+    Numeric,
+    /// Dictionary ids referring to a dictionary stored apart from the archive.
+    SharedDictionary,
+}
+
+impl Codec {
+    fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            RAW => Some(Codec::Raw),
+            COMPRESSED => Some(Codec::Compressed),
+            LZSS => Some(Codec::Lzss),
+            HUFFMAN => Some(Codec::Huffman),
+            NUMERIC => Some(Codec::Numeric),
+            SHARED_DICT => Some(Codec::SharedDictionary),
+            _ => None,
+        }
+    }
+}
+
+/// How many tokens the records are made of. A token is a dictionary id, an LZSS literal run or
+/// match, or a whole record for raw and numeric archives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct TokenStats {
+    /// Fewest tokens in a record.
+    pub min: usize,
+    /// Most tokens in a record.
+    pub max: usize,
+    /// Tokens in all records.
+    pub total: usize,
+}
+
+/// What's in an archive, and where its bytes go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub version: u8,
+    pub codec: Codec,
+    pub flags: u8,
+    pub record_kind: RecordKind,
+    pub num_records: usize,
+    pub num_sections: usize,
+    /// Only present for archives written by a version which stored them.
+    pub limits: Option<ArchiveLimits>,
+    /// Length of the whole archive, checksum included.
+    pub total_len: usize,
+    pub record_index_len: usize,
+    pub section_table_len: usize,
+    /// Number of dictionary entries. For `Codec::SharedDictionary`, these are in the shared
+    /// dictionary rather than the archive, as are the lookup table and dictionary.
+    pub dict_entries: usize,
+    pub lookup_len: usize,
+    pub dict_len: usize,
+    /// Length of the Huffman code length table.
+    pub code_table_len: usize,
+    /// Length of the records themselves, as stored by the codec.
+    pub records_len: usize,
+    pub tokens: TokenStats,
+
+    limits_len: usize,
+    record_kind_len: usize,
+}
+
+impl ArchiveInfo {
+    /// # Errors
+    ///
+    /// Returns an error if `Reader::open` would, or if a record is corrupt.
+    pub fn read<T: AsRef<[u8]> + ?Sized>(input: &T) -> Result<Self, ErrorKind> {
+        Self::read_inner(input.as_ref(), None)
+    }
+
+    /// Same as `read`, for archives which may use the shared dictionary `dict`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `Reader::open_with_dict` would, or if a record is corrupt.
+    pub fn read_with_dict<T, D>(input: &T, dict: &D) -> Result<Self, ErrorKind>
+    where
+        T: AsRef<[u8]> + ?Sized,
+        D: AsRef<[u8]> + ?Sized,
+    {
+        Self::read_inner(input.as_ref(), Some(dict.as_ref()))
+    }
+
+    fn read_inner(input: &[u8], dict: Option<&[u8]>) -> Result<Self, ErrorKind> {
+        let (reader, block_lens) = Reader::open_inner(input, dict)?;
+        let BlockLens {
+            limits: limits_len,
+            record_index: record_index_len,
+            record_kind: record_kind_len,
+            sections: section_table_len,
+        } = block_lens;
+        let PayloadParts {
+            dict_entries,
+            lookup_len,
+            dict_len,
+            code_table_len,
+            records_len,
+        } = reader.payload_parts();
+
+        let mut tokens: Option<TokenStats> = None;
+        reader.token_counts(|count| {
+            let stats = tokens.get_or_insert(TokenStats {
+                min: count,
+                max: count,
+                total: 0,
+            });
+            stats.min = stats.min.min(count);
+            stats.max = stats.max.max(count);
+            stats.total += count;
+        })?;
+
+        let codec = reader.codec();
+        Ok(Self {
+            version: input[VERSION_ADDR],
+            codec: Codec::from_id(codec).ok_or(ErrorKind::UnknownCodec(codec))?,
+            flags: input[FLAGS_ADDR],
+            record_kind: reader.record_kind(),
+            num_records: reader.num_records(),
+            num_sections: reader.num_sections(),
+            limits: reader.limits(),
+            total_len: input.len(),
+            record_index_len,
+            section_table_len,
+            dict_entries,
+            lookup_len,
+            dict_len,
+            code_table_len,
+            records_len,
+            tokens: tokens.unwrap_or_default(),
+            limits_len,
+            record_kind_len,
+        })
+    }
+
+    /// Names each part of the archive along with the bytes it takes up, in the order they're
+    /// stored. Parts the archive doesn't have are left out.
+    pub fn regions(&self) -> impl Iterator<Item = (&'static str, Range<usize>)> {
+        // A shared dictionary isn't part of the archive.
+        let (lookup_len, dict_len) = if self.codec == Codec::SharedDictionary {
+            (0, 0)
+        } else {
+            (self.lookup_len, self.dict_len)
+        };
+
+        let payload_len = self.total_len
+            - HEADER_LEN
+            - self.limits_len
+            - self.record_index_len
+            - self.record_kind_len
+            - self.section_table_len
+            - CHECKSUM_LEN;
+        let payload_header_len =
+            payload_len - lookup_len - dict_len - self.code_table_len - self.records_len;
+
+        let lens = [
+            ("magic", MAGIC_ADDR.len()),
+            ("version", 1),
+            ("codec", 1),
+            ("flags", 1),
+            ("limits", self.limits_len),
+            ("record index", self.record_index_len),
+            ("record kind", self.record_kind_len),
+            ("section table", self.section_table_len),
+            ("payload header", payload_header_len),
+            ("lookup table", lookup_len),
+            ("dictionary", dict_len),
+            ("code lengths", self.code_table_len),
+            ("records", self.records_len),
+            ("checksum", CHECKSUM_LEN),
+        ];
+
+        let mut start = 0;
+        IntoIterator::into_iter(lens).filter_map(move |(name, len)| {
+            let region = start..start + len;
+            start += len;
+            Some((name, region)).filter(|_| len > 0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Writer;
+
+    fn check_regions(info: &ArchiveInfo) {
+        let mut end = 0;
+        for (_, region) in info.regions() {
+            assert_eq!(region.start, end);
+            end = region.end;
+        }
+        assert_eq!(end, info.total_len);
+    }
+
+    #[test]
+    fn raw_archive() {
+        let mut writer = Writer::new();
+        writer.add_record("ab");
+        writer.add_record("c");
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let info = ArchiveInfo::read(&output).unwrap();
+        assert_eq!(info.codec, Codec::Raw);
+        assert_eq!(info.num_records, 2);
+        assert_eq!(
+            info.limits,
+            Some(ArchiveLimits {
+                num_records: 2,
+                max_record_len: 2,
+                max_decoded_len: 2
+            })
+        );
+        assert_eq!(info.record_kind, RecordKind::Bytes);
+        assert_eq!(info.total_len, output.len());
+        assert_eq!(info.dict_entries, 0);
+        assert_eq!(
+            info.tokens,
+            TokenStats {
+                min: 1,
+                max: 1,
+                total: 2
+            }
+        );
+
+        // A record count, then each record's length and contents.
+        let regions: Vec<_> = info.regions().collect();
+        assert_eq!(
+            regions,
+            [
+                ("magic", 0..4),
+                ("version", 4..5),
+                ("codec", 5..6),
+                ("flags", 6..7),
+                ("limits", 7..10),
+                ("payload header", 10..11),
+                ("records", 11..16),
+                ("checksum", 16..20),
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_archive() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        writer.begin_section("first");
+        for line in &lines {
+            writer.add_record(*line);
+        }
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let info = ArchiveInfo::read(&output).unwrap();
+        assert!(matches!(info.codec, Codec::Compressed | Codec::Huffman));
+        assert_eq!(info.num_records, lines.len());
+        assert_eq!(info.num_sections, 1);
+        assert!(info.dict_entries > 0 && info.dict_len > 0 && info.lookup_len > 0);
+        assert!(info.record_index_len > 0 && info.section_table_len > 0);
+        assert!(info.tokens.min <= info.tokens.max);
+        assert!(info.tokens.total >= lines.len());
+        check_regions(&info);
+
+        // Integers get a record kind block.
+        let mut writer = Writer::new();
+        for val in &[5, 10, 20] {
+            writer.add_u64(*val);
+        }
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let info = ArchiveInfo::read(&output).unwrap();
+        assert_eq!(info.record_kind, RecordKind::Unsigned);
+        assert!(info.regions().any(|(name, _)| name == "record kind"));
+        check_regions(&info);
+
+        assert!(matches!(
+            ArchiveInfo::read(&output[1..]),
+            Err(ErrorKind::InvalidMagic)
+        ));
+    }
+}
@@ -1,238 +1,2807 @@
 #[cfg(any(feature = "std", test))]
-use std::convert::TryInto;
+use crate::compress::{Compress, CompressOptions, CompressionLevel, SharedDictionary};
 
 #[cfg(any(feature = "std", test))]
-use crate::compress::Compress;
+use std::borrow::Cow;
 
-use crate::{decompress::Decompress, ErrorKind, RunLengthEncoded};
+#[cfg(any(feature = "std", test))]
+use crate::{addr_width, lzss, write_addr, zigzag_encode, FORMAT_VERSION};
+
+use core::convert::TryFrom;
+
+use crate::{
+    crc::crc32,
+    decompress::Decompress,
+    lzss::Lzss,
+    numeric::{self, Numeric},
+    read_addr, zigzag_decode, ArchiveLimits, ErrorKind, FromRecord, IntegerRecords, Layout, Leb128,
+    MapRecords, MapStrRecords, ParsedRecords, RecordKind, ADDR_SIZE, CHECKSUM_LEN, CODEC_ADDR,
+    FLAGS_ADDR, FLAG_RECORD_INDEX, FLAG_RECORD_KIND, FLAG_SECTIONS, HEADER_LEN, KNOWN_FLAGS,
+    LEB128_MAX_LEN, LIMITS_FORMAT_VERSION, MAGIC, MAGIC_ADDR, VERSION_ADDR,
+};
+
+pub(crate) const COMPRESSED: u8 = 1;
+pub(crate) const RAW: u8 = 0;
+pub(crate) const LZSS: u8 = 2;
+pub(crate) const HUFFMAN: u8 = 3;
+pub(crate) const NUMERIC: u8 = 4;
+pub(crate) const SHARED_DICT: u8 = 5;
 
-const COMPRESSED: u8 = 1;
-const RAW: u8 = 0;
+// Lengths of the optional blocks between the header and the payload, 0 if not present.
+#[derive(Default)]
+pub(crate) struct BlockLens {
+    pub(crate) limits: usize,
+    pub(crate) record_index: usize,
+    pub(crate) record_kind: usize,
+    pub(crate) sections: usize,
+}
+
+// Where the bytes of a payload go. Only dictionary codecs have a lookup table and dictionary,
+// and only Huffman coded ones have a code length table.
+#[derive(Default)]
+pub(crate) struct PayloadParts {
+    pub(crate) dict_entries: usize,
+    pub(crate) lookup_len: usize,
+    pub(crate) dict_len: usize,
+    pub(crate) code_table_len: usize,
+    pub(crate) records_len: usize,
+}
 
-pub enum Reader<'a> {
+#[derive(Clone)]
+enum Payload<'a> {
     Compressed(Decompress<'a>),
     Raw(Raw<'a>),
+    Lzss(Lzss<'a>),
+    Numeric(Numeric<'a>),
 }
 
-impl<'a> Reader<'a> {
-    /// # Errors
-    ///
-    /// Will return an error if the length of `input` is 0, the first bit is not `RAW` or `COMPRESSED`,
-    /// or if `Compressed::open` or `Raw::open` fail.
-    pub fn open<T: AsRef<[u8]> + ?Sized + 'a>(input: &'a T) -> Result<Reader, ErrorKind> {
-        let input = input.as_ref();
-        let (is_compressed, data) = input
-            .split_first()
-            .ok_or(ErrorKind::InvalidCompressedFlag)?;
-
-        match *is_compressed {
-            RAW => Ok(Reader::Raw(Raw::open(data)?)),
-            COMPRESSED => Ok(Reader::Compressed(Decompress::open(data))),
-            _ => Err(ErrorKind::InvalidCompressedFlag),
+impl<'a> Payload<'a> {
+    // `dict` is only needed by archives compressed against a shared dictionary.
+    fn open(
+        codec: u8,
+        data: &'a [u8],
+        layout: Layout,
+        dict: Option<&'a [u8]>,
+    ) -> Result<Self, ErrorKind> {
+        match codec {
+            RAW => Ok(Payload::Raw(Raw::open(data, layout)?)),
+            COMPRESSED => Ok(Payload::Compressed(Decompress::open(data, layout)?)),
+            // Version 3 predates these codecs.
+            LZSS if layout == Layout::Wide => Ok(Payload::Lzss(Lzss::open(data)?)),
+            HUFFMAN if layout == Layout::Wide => {
+                Ok(Payload::Compressed(Decompress::open_huffman(data)?))
+            }
+            NUMERIC if layout == Layout::Wide => Ok(Payload::Numeric(Numeric::open(data)?)),
+            SHARED_DICT if layout == Layout::Wide => {
+                let dict = dict.ok_or(ErrorKind::DictionaryMismatch)?;
+                Ok(Payload::Compressed(Decompress::open_with_dict(data, dict)?))
+            }
+            codec => Err(ErrorKind::UnknownCodec(codec)),
         }
     }
 
-    #[must_use]
-    pub fn num_records(&self) -> usize {
+    fn num_records(&self) -> usize {
         match self {
             Self::Compressed(c) => c.num_records(),
             Self::Raw(r) => r.num_records(),
+            Self::Lzss(l) => l.num_records(),
+            Self::Numeric(n) => n.num_records(),
         }
     }
 
-    /// Reads the next record from the file.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error:
-    ///
-    /// * On failure to read record length.
-    /// * Record length exceeds output buffer length.
-    /// * Record length exceeds remaining file length.
-    pub fn next_record<'b>(&mut self, dst: &'b mut [u8]) -> Result<Option<&'b [u8]>, ErrorKind> {
+    fn position(&self) -> usize {
         match self {
-            Self::Compressed(c) => c.next_record(dst),
-            Self::Raw(r) => match r.next_record()? {
-                None => Ok(None),
-                Some(record) => {
-                    if record.len() > dst.len() {
-                        Err(ErrorKind::RecordReadError)
-                    } else {
-                        let written_buf = &mut dst[..record.len()];
-                        written_buf.copy_from_slice(record);
-                        Ok(Some(written_buf))
-                    }
-                }
+            Self::Compressed(c) => c.position(),
+            Self::Raw(r) => r.position(),
+            Self::Lzss(l) => l.position(),
+            Self::Numeric(n) => n.position(),
+        }
+    }
+
+    fn set_position(&mut self, position: usize) {
+        match self {
+            Self::Compressed(c) => c.set_position(position),
+            Self::Raw(r) => r.set_position(position),
+            Self::Lzss(l) => l.set_position(position),
+            Self::Numeric(n) => n.set_position(position),
+        }
+    }
+
+    fn end_position(&self) -> usize {
+        match self {
+            Self::Compressed(c) => c.end_position(),
+            Self::Raw(r) => r.end_position(),
+            Self::Lzss(l) => l.end_position(),
+            Self::Numeric(n) => n.end_position(),
+        }
+    }
+
+    fn parts(&self) -> PayloadParts {
+        match self {
+            Self::Compressed(c) => PayloadParts {
+                dict_entries: c.num_dict_entries(),
+                lookup_len: c.lookup_len(),
+                dict_len: c.dict_len(),
+                code_table_len: c.code_table_len(),
+                records_len: c.end_position(),
+            },
+            Self::Raw(r) => PayloadParts {
+                records_len: r.end_position(),
+                ..PayloadParts::default()
+            },
+            Self::Lzss(l) => PayloadParts {
+                records_len: l.end_position(),
+                ..PayloadParts::default()
             },
+            Self::Numeric(n) => PayloadParts {
+                records_len: n.packed_len(),
+                ..PayloadParts::default()
+            },
+        }
+    }
+
+    // Moves past the next record, returning the number of tokens it's made of. Raw and numeric
+    // records are a single token.
+    fn count_tokens(&mut self) -> Result<Option<usize>, ErrorKind> {
+        match self {
+            Self::Compressed(c) => c.count_tokens(),
+            Self::Raw(r) => Ok(r.next_record()?.map(|_| 1)),
+            Self::Lzss(l) => l.count_tokens(),
+            Self::Numeric(n) => Ok(n.next_value()?.map(|_| 1)),
+        }
+    }
+
+    // Length of the next record once decoded, without moving past it.
+    fn record_len(&self, kind: RecordKind) -> Result<Option<usize>, ErrorKind> {
+        match self {
+            Self::Compressed(c) => c.record_len(),
+            Self::Raw(r) => Ok(r.clone().next_record()?.map(<[u8]>::len)),
+            Self::Lzss(l) => l.record_len(),
+            Self::Numeric(n) => Ok(n.clone().next_value()?.map(|val| {
+                let mut buf = [0_u8; numeric::MAX_RECORD_LEN];
+                numeric::record_bytes(kind, val, &mut buf).len()
+            })),
+        }
+    }
+
+    // Checks that every dictionary entry lies inside the dictionary, whether or not any record
+    // uses it.
+    fn check_dictionary(&self) -> Result<(), ErrorKind> {
+        match self {
+            Self::Compressed(c) => c.dict_entries().try_for_each(|entry| entry.map(|_| ())),
+            Self::Raw(_) | Self::Lzss(_) | Self::Numeric(_) => Ok(()),
+        }
+    }
+
+    // Whether there's nothing left after the current position, as there shouldn't be once every
+    // record has been read.
+    fn at_end(&self) -> bool {
+        match self {
+            Self::Numeric(n) => n.position() == n.end_position() && n.trailing_len() == 0,
+            _ => self.position() == self.end_position(),
+        }
+    }
+
+    fn skip_record(&mut self) -> Result<Option<()>, ErrorKind> {
+        match self {
+            Self::Compressed(c) => c.skip_record(),
+            Self::Raw(r) => Ok(r.next_record()?.map(|_| ())),
+            Self::Lzss(l) => l.skip_record(),
+            Self::Numeric(n) => Ok(n.next_value()?.map(|_| ())),
         }
     }
 }
 
-pub struct Raw<'a> {
-    num_records: usize,
-    records: &'a [u8],
-    current_record: usize,
+// Offset of each record into the payload.
+#[derive(Clone)]
+struct RecordIndex<'a> {
+    offsets: &'a [u8],
+    width: usize,
 }
 
-impl<'a> Raw<'a> {
-    fn open<T: AsRef<[u8]> + ?Sized + 'a>(data: &'a T) -> Result<Self, ErrorKind> {
-        let data = data.as_ref();
-        let (num_records, records) =
-            RunLengthEncoded::decode(data).ok_or(ErrorKind::LengthDecode)?;
+impl<'a> RecordIndex<'a> {
+    // Version 3 stores the number of entries as a 16-bit value followed by 16-bit entries.
+    // Later versions store the number of entries, followed by the width of each entry.
+    fn open(data: &'a [u8], layout: Layout) -> Result<(Self, &'a [u8]), ErrorKind> {
+        let (num_entries, width, rest) = match layout {
+            Layout::Narrow => {
+                let num_entries =
+                    read_addr(data, 0, ADDR_SIZE).ok_or(ErrorKind::IndexOutOfRange)?;
+                (num_entries, ADDR_SIZE, &data[ADDR_SIZE..])
+            }
+            Layout::Wide => {
+                let (num_entries, rest) =
+                    Leb128::decode_usize(data).ok_or(ErrorKind::IndexOutOfRange)?;
+                let (&width, rest) = rest.split_first().ok_or(ErrorKind::IndexOutOfRange)?;
+                (num_entries, width as usize, rest)
+            }
+        };
 
-        Ok(Self {
-            num_records: num_records as usize,
-            records,
-            current_record: 0,
-        })
+        if width == 0 || width > core::mem::size_of::<usize>() {
+            return Err(ErrorKind::IndexOutOfRange);
+        }
+
+        let index_len = num_entries
+            .checked_mul(width)
+            .ok_or(ErrorKind::IndexOutOfRange)?;
+        let offsets = rest.get(..index_len).ok_or(ErrorKind::IndexOutOfRange)?;
+
+        Ok((Self { offsets, width }, &rest[index_len..]))
     }
 
-    #[must_use]
-    pub fn num_records(&self) -> usize {
-        self.num_records
+    fn len(&self) -> usize {
+        self.offsets.len() / self.width
     }
 
-    /// Reads the next record from the file.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error:
-    ///
-    /// * On failure to read record length.
-    /// * Record length exceeds output buffer length.
-    /// * Record length exceeds remaining file length.
-    pub fn next_record(&mut self) -> Result<Option<&'a [u8]>, ErrorKind> {
-        if self.current_record == self.records.len() {
-            return Ok(None);
+    fn get(&self, n: usize) -> Option<usize> {
+        read_addr(self.offsets, n.checked_mul(self.width)?, self.width)
+    }
+}
+
+// Name and record count of each section. Sections cover consecutive records, in order.
+#[derive(Clone, Copy)]
+struct SectionTable<'a> {
+    entries: &'a [u8],
+    len: usize,
+}
+
+impl<'a> SectionTable<'a> {
+    fn open(data: &'a [u8]) -> Result<(Self, &'a [u8]), ErrorKind> {
+        let (len, entries) = Leb128::decode_usize(data).ok_or(ErrorKind::SectionOutOfRange)?;
+
+        let mut rest = entries;
+        for _ in 0..len {
+            let (_, _, next) = Self::entry(rest).ok_or(ErrorKind::SectionOutOfRange)?;
+            rest = next;
         }
 
-        let (len, remaining_bytes) = RunLengthEncoded::decode(&self.records[self.current_record..])
-            .ok_or(ErrorKind::RecordReadError)?;
-        let len = len as usize;
+        let table_len = entries.len() - rest.len();
+        Ok((
+            Self {
+                entries: &entries[..table_len],
+                len,
+            },
+            rest,
+        ))
+    }
 
-        let record = remaining_bytes
-            .get(..len)
-            .ok_or(ErrorKind::RecordReadError)?;
+    // Splits the name and record count of one section off the front of `data`.
+    fn entry(data: &'a [u8]) -> Option<(&'a [u8], usize, &'a [u8])> {
+        let (name_len, rest) = Leb128::decode_usize(data)?;
+        let name = rest.get(..name_len)?;
+        let (num_records, rest) = Leb128::decode_usize(&rest[name_len..])?;
+        Some((name, num_records, rest))
+    }
 
-        let len_dif =
-            self.records.len() - self.current_record - (remaining_bytes.len() - record.len());
-        self.current_record += len_dif;
+    // Yields the name, first record and record count of each section.
+    fn iter(self) -> impl Iterator<Item = (&'a [u8], usize, usize)> {
+        let mut data = self.entries;
+        let mut first_record = 0_usize;
+        core::iter::from_fn(move || {
+            let (name, num_records, rest) = Self::entry(data)?;
+            data = rest;
 
-        Ok(Some(record))
+            let section = (name, first_record, num_records);
+            first_record = first_record.saturating_add(num_records);
+            Some(section)
+        })
     }
 }
 
-#[cfg(any(feature = "std", test))]
-#[derive(Default)]
-pub struct Writer<'a> {
-    raw_records: Vec<&'a [u8]>,
-    compressor: Compress,
+#[derive(Clone)]
+pub struct Reader<'a> {
+    payload: Payload<'a>,
+    codec: u8,
+    // Only present if the archive was written by a version which stored them.
+    limits: Option<ArchiveLimits>,
+    // Only present if the archive was written with an index.
+    record_index: Option<RecordIndex<'a>>,
+    record_kind: RecordKind,
+    // Only present if the archive was written with sections.
+    sections: Option<SectionTable<'a>>,
+
+    // The records this reader covers. That's the whole archive, unless it was returned by
+    // `section`.
+    first_record: usize,
+    first_position: usize,
+    num_records: usize,
+
+    // Relative to `first_record`.
+    current_record: usize,
 }
 
-#[cfg(any(feature = "std", test))]
-impl<'a> Writer<'a> {
-    #[must_use]
-    pub fn new() -> Self {
-        Self {
-            raw_records: Vec::new(),
-            compressor: Compress::new(),
-        }
+impl<'a> Reader<'a> {
+    /// # Errors
+    ///
+    /// Will return an error if `input` does not start with the archive magic, was written by an
+    /// unsupported format version or with unknown flags, fails the checksum, the codec is not
+    /// `RAW`, `COMPRESSED`, `LZSS`, `HUFFMAN` or `NUMERIC`, the record index or section table is
+    /// truncated, or if opening the payload fails. Archives compressed against a shared dictionary
+    /// need `open_with_dict`.
+    pub fn open<T: AsRef<[u8]> + ?Sized + 'a>(input: &'a T) -> Result<Reader<'a>, ErrorKind> {
+        Self::open_inner(input.as_ref(), None).map(|(reader, _)| reader)
     }
 
-    pub fn preload_dict<T: AsRef<[u8]> + ?Sized + 'a>(&mut self, entries: &[&T]) {
-        entries
-            .iter()
-            .for_each(|e| self.compressor.add_dictionary_entry(e))
+    /// Same as `open`, but also accepts archives compressed against `dict`, a shared dictionary
+    /// stored by `SharedDictionary::store`. Archives with a dictionary of their own ignore it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `open` would, or, for an archive which uses a shared dictionary, if
+    /// `dict` isn't a valid dictionary or isn't the one the archive was compressed against.
+    pub fn open_with_dict<T, D>(input: &'a T, dict: &'a D) -> Result<Reader<'a>, ErrorKind>
+    where
+        T: AsRef<[u8]> + ?Sized + 'a,
+        D: AsRef<[u8]> + ?Sized + 'a,
+    {
+        Self::open_inner(input.as_ref(), Some(dict.as_ref())).map(|(reader, _)| reader)
     }
 
-    pub fn add_record<T: AsRef<[u8]> + ?Sized + 'a>(&mut self, record: &'a T) {
-        self.compressor.add_record(record);
-        self.raw_records.push(record.as_ref());
+    // Also returns the lengths of the optional blocks, for `ArchiveInfo`.
+    pub(crate) fn open_inner(
+        input: &'a [u8],
+        dict: Option<&'a [u8]>,
+    ) -> Result<(Reader<'a>, BlockLens), ErrorKind> {
+        if input.len() < HEADER_LEN || input[MAGIC_ADDR] != MAGIC {
+            return Err(ErrorKind::InvalidMagic);
+        }
+
+        let version = input[VERSION_ADDR];
+        let layout = Layout::for_version(version).ok_or(ErrorKind::UnsupportedVersion(version))?;
+
+        let flags = input[FLAGS_ADDR];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ErrorKind::UnsupportedFlags(flags));
+        }
+
+        let (archive, checksum) = input
+            .len()
+            .checked_sub(CHECKSUM_LEN)
+            .filter(|&len| len >= HEADER_LEN)
+            .map(|len| input.split_at(len))
+            .ok_or(ErrorKind::ChecksumMismatch)?;
+
+        let mut checksum_buf = [0_u8; CHECKSUM_LEN];
+        checksum_buf.copy_from_slice(checksum);
+        if crc32(archive) != u32::from_le_bytes(checksum_buf) {
+            return Err(ErrorKind::ChecksumMismatch);
+        }
+
+        let mut data = &archive[HEADER_LEN..];
+        let mut block_lens = BlockLens::default();
+
+        let mut limits = None;
+        if version >= LIMITS_FORMAT_VERSION {
+            let (header_limits, end) =
+                ArchiveLimits::decode(archive, HEADER_LEN).ok_or(ErrorKind::HeaderOutOfRange)?;
+            limits = Some(header_limits);
+            block_lens.limits = end - HEADER_LEN;
+            data = &archive[end..];
+        }
+
+        let mut record_index = None;
+        if flags & FLAG_RECORD_INDEX != 0 {
+            let (index, rest) = RecordIndex::open(data, layout)?;
+            record_index = Some(index);
+            block_lens.record_index = data.len() - rest.len();
+            data = rest;
+        }
+
+        let mut record_kind = RecordKind::Bytes;
+        if flags & FLAG_RECORD_KIND != 0 {
+            let (&tag, rest) = data.split_first().ok_or(ErrorKind::HeaderOutOfRange)?;
+            record_kind = RecordKind::from_tag(tag).ok_or(ErrorKind::HeaderOutOfRange)?;
+            block_lens.record_kind = 1;
+            data = rest;
+        }
+
+        let mut sections = None;
+        if flags & FLAG_SECTIONS != 0 {
+            let (table, rest) = SectionTable::open(data)?;
+            sections = Some(table);
+            block_lens.sections = data.len() - rest.len();
+            data = rest;
+        }
+
+        let codec = input[CODEC_ADDR];
+        let payload = Payload::open(codec, data, layout, dict)?;
+        let num_records = payload.num_records();
+
+        if let Some(index) = &record_index {
+            if index.len() != num_records {
+                return Err(ErrorKind::IndexOutOfRange);
+            }
+        }
+
+        if limits.is_some_and(|limits| limits.num_records != num_records) {
+            return Err(ErrorKind::HeaderOutOfRange);
+        }
+
+        if let Some(table) = sections {
+            let total = table
+                .iter()
+                .try_fold(0_usize, |total, (_, _, len)| total.checked_add(len));
+            if total != Some(num_records) {
+                return Err(ErrorKind::SectionOutOfRange);
+            }
+        }
+
+        let reader = Reader {
+            payload,
+            codec,
+            limits,
+            record_index,
+            record_kind,
+            sections,
+            first_record: 0,
+            first_position: 0,
+            num_records,
+            current_record: 0,
+        };
+        Ok((reader, block_lens))
     }
 
+    /// Reads every record in the whole archive without producing any output, even for a reader
+    /// returned by `section`, to check it's intact. Opening an archive only checks its header,
+    /// so this finds what would otherwise only show up once the bad record is read.
+    ///
     /// # Errors
     ///
-    /// Returns error on failure to write to the `writer`.
-    pub fn write(&self, mut writer: impl std::io::Write) -> Result<(), ErrorKind> {
-        let mut raw = Vec::with_capacity(self.raw_records.iter().map(|r| r.len() + 2).sum());
+    /// Returns an error if:
+    ///
+    /// * A record is truncated or corrupt, or uses an id outside of the lookup table.
+    /// * An entry of the lookup table lies outside of the dictionary.
+    /// * There are fewer records than the archive claims, or data left after the last one.
+    /// * The record index doesn't point at each record.
+    /// * The limits in the header don't match the records.
+    pub fn validate(&self) -> Result<(), ErrorKind> {
+        self.payload.check_dictionary()?;
 
-        self.raw_records.iter().for_each(|r| {
-            raw.extend_from_slice(RunLengthEncoded::encode(r.len().try_into().unwrap()).as_ref());
-            raw.extend_from_slice(r);
-        });
+        let mut reader = Reader {
+            first_record: 0,
+            first_position: 0,
+            num_records: self.payload.num_records(),
+            current_record: 0,
+            ..self.clone()
+        };
+        reader.payload.set_position(0);
 
-        let compressed = self.compressor.store_archive();
+        let mut max_record_len = 0;
+        let mut max_decoded_len = 0;
+        for i in 0..reader.num_records {
+            if let Some(index) = &reader.record_index {
+                if index.get(i) != Some(reader.payload.position()) {
+                    return Err(ErrorKind::IndexOutOfRange);
+                }
+            }
 
-        if compressed.len() > raw.len() {
-            writer.write_all(&[RAW])?;
-            writer.write_all(
-                RunLengthEncoded::encode(self.raw_records.len().try_into().unwrap()).as_ref(),
-            )?;
-            writer.write_all(&raw)?;
-        } else {
-            writer.write_all(&[COMPRESSED])?;
-            writer.write_all(&compressed)?;
+            // Integer records are short enough to keep, so that their value can be measured.
+            let mut buf = [0_u8; LEB128_MAX_LEN];
+            let mut len = 0;
+            let found = reader.stream_record(|piece| {
+                if let Some(dst) = buf.get_mut(len..len + piece.len()) {
+                    dst.copy_from_slice(piece);
+                }
+                len += piece.len();
+            })?;
+            if found.is_none() {
+                return Err(ErrorKind::RecordsOutOfRange);
+            }
+
+            let decoded_len = match reader.record_kind {
+                RecordKind::Bytes => len,
+                kind => match buf.get(..len).and_then(Leb128::decode) {
+                    Some((val, [])) => integer_len(kind, val),
+                    _ => return Err(ErrorKind::InvalidInteger),
+                },
+            };
+            max_record_len = max_record_len.max(len);
+            max_decoded_len = max_decoded_len.max(decoded_len);
+        }
+
+        if !reader.payload.at_end() {
+            return Err(ErrorKind::RecordsOutOfRange);
+        }
+
+        if let Some(limits) = self.limits {
+            if limits.max_record_len != max_record_len || limits.max_decoded_len != max_decoded_len
+            {
+                return Err(ErrorKind::HeaderOutOfRange);
+            }
         }
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    pub(crate) fn codec(&self) -> u8 {
+        self.codec
+    }
 
-    #[test]
-    fn round_trip_compressed() {
-        // Expects a file that does compress. The input from AoC 2020 day 7 is an example.
-        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
-        let mut writer = Writer::new();
+    pub(crate) fn payload_parts(&self) -> PayloadParts {
+        self.payload.parts()
+    }
 
-        let mut num_records = 0;
-        for line in input_text.lines() {
-            writer.add_record(line);
-            num_records += 1;
+    // Passes the number of tokens in each record this reader covers to `f`, without moving the
+    // reader.
+    pub(crate) fn token_counts(&self, mut f: impl FnMut(usize)) -> Result<(), ErrorKind> {
+        let mut payload = self.payload.clone();
+        payload.set_position(self.first_position);
+        for i in 0..self.num_records {
+            let count = payload.count_tokens();
+            match count.map_err(|e| e.in_record(self.first_record + i))? {
+                Some(count) => f(count),
+                None => break,
+            }
         }
 
-        let mut output: Vec<u8> = Vec::new();
-        writer.write(&mut output).unwrap();
+        Ok(())
+    }
 
-        assert_eq!(COMPRESSED, output[0]);
+    /// Sizes of the records in the whole archive, even for a reader returned by `section`, or
+    /// `None` for archives written before they were stored. Also readable without opening the
+    /// archive, by `ArchiveLimits::read`.
+    #[must_use]
+    pub fn limits(&self) -> Option<ArchiveLimits> {
+        self.limits
+    }
 
-        let mut reader = Reader::open(&output).unwrap();
-        assert_eq!(num_records, reader.num_records());
+    /// Number of records covered by this reader. For a reader returned by `section`, that's the
+    /// number of records in the section.
+    #[must_use]
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
 
-        let mut buf = [0_u8; 512];
-        for (i, line) in input_text.lines().enumerate() {
-            let record = reader.next_record(&mut buf).unwrap();
-            assert_eq!(record, Some(line.as_bytes()), "{}: {}", i, line);
-        }
+    #[must_use]
+    pub fn record_kind(&self) -> RecordKind {
+        self.record_kind
     }
 
-    #[test]
-    fn round_trip_raw() {
-        // Expects a file that doesn't compress. The input from AoC 2020 day 2 is an example.
-        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
-        let mut writer = Writer::new();
+    #[must_use]
+    pub fn has_record_index(&self) -> bool {
+        self.record_index.is_some()
+    }
 
-        let mut num_records = 0;
-        for line in input_text.lines() {
-            writer.add_record(line);
-            num_records += 1;
+    /// Number of sections in the archive, or 0 if it was written without any.
+    #[must_use]
+    pub fn num_sections(&self) -> usize {
+        self.sections.map_or(0, |table| table.len)
+    }
+
+    /// Name of section `i`, or `None` if the archive has fewer sections.
+    #[must_use]
+    pub fn section_name(&self, i: usize) -> Option<&'a [u8]> {
+        self.sections?.iter().nth(i).map(|(name, _, _)| name)
+    }
+
+    /// Returns a reader over the records of section `i`, positioned at its first record.
+    ///
+    /// Returns `None` if the archive has fewer sections.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking to the start of the section fails.
+    pub fn section(&self, i: usize) -> Result<Option<Reader<'a>>, ErrorKind> {
+        let Some((_, first_record, num_records)) = self.sections.and_then(|t| t.iter().nth(i))
+        else {
+            return Ok(None);
+        };
+
+        let mut section = Reader {
+            first_record: 0,
+            first_position: 0,
+            num_records: self.payload.num_records(),
+            current_record: 0,
+            ..self.clone()
+        };
+        section.seek(first_record)?;
+
+        section.first_record = first_record;
+        section.first_position = section.payload.position();
+        section.num_records = num_records;
+        section.current_record = 0;
+        Ok(Some(section))
+    }
+
+    /// Same as `section`, but looks the section up by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking to the start of the section fails.
+    pub fn section_named<T: AsRef<[u8]> + ?Sized>(
+        &self,
+        name: &T,
+    ) -> Result<Option<Reader<'a>>, ErrorKind> {
+        let name = name.as_ref();
+        match self
+            .sections
+            .and_then(|t| t.iter().position(|(n, _, _)| n == name))
+        {
+            Some(i) => self.section(i),
+            None => Ok(None),
         }
+    }
 
-        let mut output: Vec<u8> = Vec::new();
-        writer.write(&mut output).unwrap();
+    /// Moves the reader so that the next call to `next_record` returns record `n`.
+    /// Seeking past the last record moves to the end of the archive.
+    ///
+    /// Uses the record index if the archive has one, otherwise it scans forward from the first
+    /// record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record before `n` could not be read while scanning.
+    pub fn seek(&mut self, n: usize) -> Result<(), ErrorKind> {
+        let n = n.min(self.num_records);
 
-        assert_eq!(RAW, output[0]);
+        if let Some(index) = &self.record_index {
+            let target = self.first_record + n;
+            let position = if target < self.payload.num_records() {
+                index.get(target).ok_or(ErrorKind::IndexOutOfRange)?
+            } else {
+                self.payload.end_position()
+            };
+            self.payload.set_position(position);
+        } else {
+            self.payload.set_position(self.first_position);
+            for i in 0..n {
+                let skipped = self.payload.skip_record();
+                if skipped
+                    .map_err(|e| e.in_record(self.first_record + i))?
+                    .is_none()
+                {
+                    break;
+                }
+            }
+        }
 
-        let mut reader = Reader::open(&output).unwrap();
-        assert_eq!(num_records, reader.num_records());
+        self.current_record = n;
+        Ok(())
+    }
 
-        let mut buf = [0_u8; 512];
-        for (i, line) in input_text.lines().enumerate() {
-            let record = reader.next_record(&mut buf).unwrap();
-            assert_eq!(record, Some(line.as_bytes()), "{}: {}", i, line);
+    /// Reads record `n`, leaving the reader positioned after it.
+    ///
+    /// Returns `None` if `n` is past the last record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking to the record fails, or if `next_record` fails.
+    pub fn record<'b>(
+        &mut self,
+        n: usize,
+        dst: &'b mut [u8],
+    ) -> Result<Option<&'b [u8]>, ErrorKind> {
+        if n >= self.num_records() {
+            return Ok(None);
         }
+
+        self.seek(n)?;
+        self.next_record(dst)
+    }
+
+    /// Returns an iterator which reads each remaining record into `buf` and yields the result of
+    /// passing it to `f`.
+    pub fn map_records<'r, 'b, F, T>(
+        &'r mut self,
+        buf: &'b mut [u8],
+        f: F,
+    ) -> MapRecords<'r, 'a, 'b, F>
+    where
+        F: FnMut(&[u8]) -> T,
+    {
+        MapRecords::new(self, buf, f)
+    }
+
+    /// Same as `map_records`, but checks that each record is valid UTF-8 before passing it to `f`.
+    pub fn map_str_records<'r, 'b, F, T>(
+        &'r mut self,
+        buf: &'b mut [u8],
+        f: F,
+    ) -> MapStrRecords<'r, 'a, 'b, F>
+    where
+        F: FnMut(&str) -> T,
+    {
+        MapStrRecords::new(self, buf, f)
+    }
+
+    /// Returns an iterator over the remaining records of an archive of `RecordKind::Unsigned`
+    /// records.
+    pub fn u64_records<'r>(&'r mut self) -> IntegerRecords<'r, 'a, u64> {
+        IntegerRecords::new(self, Reader::next_u64)
+    }
+
+    /// Returns an iterator over the remaining records of an archive of `RecordKind::Signed`
+    /// records.
+    pub fn i64_records<'r>(&'r mut self) -> IntegerRecords<'r, 'a, i64> {
+        IntegerRecords::new(self, Reader::next_i64)
+    }
+
+    /// Returns an iterator which reads each remaining record into `buf` and parses it as a `T`.
+    pub fn parsed_records<'r, 'b, T: FromRecord>(
+        &'r mut self,
+        buf: &'b mut [u8],
+    ) -> ParsedRecords<'r, 'a, 'b, T> {
+        ParsedRecords::new(self, buf)
+    }
+
+    /// Reads the next record into `buf` and parses it as a `T`. Records of an archive of integers
+    /// are converted with `FromRecord::from_integer` instead, without using `buf`, as are text
+    /// records stored as integers if `T` is an integer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record doesn't parse as a `T`, or if reading it fails.
+    pub fn next_parsed<T: FromRecord>(&mut self, buf: &mut [u8]) -> Result<Option<T>, ErrorKind> {
+        let n = self.first_record + self.current_record;
+        let parsed = match self.record_kind {
+            RecordKind::Bytes => match self.next_numeric() {
+                // Only formatted as text if `T` can't take the integer, so that it parses the
+                // same whichever codec was picked.
+                Some(val) => val?.map(|val| {
+                    T::from_integer(val.into()).or_else(|_| {
+                        let mut text = [0_u8; numeric::MAX_RECORD_LEN];
+                        T::from_record(numeric::record_bytes(RecordKind::Bytes, val, &mut text))
+                    })
+                }),
+                None => self.next_record(buf)?.map(T::from_record),
+            },
+            RecordKind::Unsigned => self.next_u64()?.map(|val| T::from_integer(val.into())),
+            RecordKind::Signed => self.next_i64()?.map(|val| T::from_integer(val.into())),
+        };
+
+        parsed.transpose().map_err(|e| e.in_record(n))
+    }
+
+    /// Reads the next record of an archive of `RecordKind::Unsigned` records. Use `next_parsed`
+    /// to read integers from text records.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive holds a different kind of record, the record is not a
+    /// valid integer, or if `next_record` fails.
+    pub fn next_u64(&mut self) -> Result<Option<u64>, ErrorKind> {
+        if self.record_kind != RecordKind::Unsigned {
+            return Err(ErrorKind::WrongRecordKind);
+        }
+
+        if let Some(val) = self.next_numeric() {
+            return val?
+                .map(|val| u64::try_from(val).map_err(|_| ErrorKind::InvalidInteger))
+                .transpose();
+        }
+
+        self.next_integer()
+    }
+
+    /// Reads the next record of an archive of `RecordKind::Signed` records. Use `next_parsed`
+    /// to read integers from text records.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive holds a different kind of record, the record is not a
+    /// valid integer, or if `next_record` fails.
+    pub fn next_i64(&mut self) -> Result<Option<i64>, ErrorKind> {
+        if self.record_kind != RecordKind::Signed {
+            return Err(ErrorKind::WrongRecordKind);
+        }
+
+        if let Some(val) = self.next_numeric() {
+            return val;
+        }
+
+        Ok(self.next_integer()?.map(zigzag_decode))
+    }
+
+    // Archives stored with the numeric codec hold the values themselves, so there's no need to
+    // encode them as records just to decode them again. Returns `None` for other codecs.
+    fn next_numeric(&mut self) -> Option<Result<Option<i64>, ErrorKind>> {
+        let Payload::Numeric(n) = &mut self.payload else {
+            return None;
+        };
+
+        if self.current_record >= self.num_records {
+            return Some(Ok(None));
+        }
+
+        let val = n.next_value();
+        if let Ok(Some(_)) = val {
+            self.current_record += 1;
+        }
+        Some(val)
+    }
+
+    fn next_integer(&mut self) -> Result<Option<u64>, ErrorKind> {
+        let mut buf = [0_u8; LEB128_MAX_LEN];
+        let record = match self.next_record(&mut buf) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Ok(None),
+            Err(ErrorKind::BufferTooSmall { .. }) => return Err(ErrorKind::InvalidInteger),
+            Err(e) => return Err(e),
+        };
+
+        match Leb128::decode(record) {
+            Some((val, [])) => Ok(Some(val)),
+            _ => Err(ErrorKind::InvalidInteger),
+        }
+    }
+
+    /// Length of the next record as `next_record` would return it, without moving past it, so
+    /// that a buffer can be checked or sized first. Integers are counted LEB128 encoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated or corrupt.
+    pub fn next_record_len(&self) -> Result<Option<usize>, ErrorKind> {
+        if self.current_record >= self.num_records {
+            return Ok(None);
+        }
+
+        self.payload
+            .record_len(self.record_kind)
+            .map_err(|e| e.in_record(self.first_record + self.current_record))
+    }
+
+    /// Reads the next record from the file, passing it to `f` in one or more pieces.
+    /// Unlike `next_record`, this doesn't need a buffer large enough to hold the whole record.
+    ///
+    /// Returns the total length of the record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * Record length exceeds remaining file length.
+    pub fn stream_record(&mut self, f: impl FnMut(&[u8])) -> Result<Option<usize>, ErrorKind> {
+        self.stream_record_with_window(&mut [], f)
+    }
+
+    /// Same as `stream_record`, but LZSS archives keep the last `window.len()` bytes of the
+    /// record in `window`, so that matches are copied from it instead of being followed back
+    /// through the record, which is slow for long records. Other codecs don't use it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `stream_record` would.
+    pub fn stream_record_with_window(
+        &mut self,
+        window: &mut [u8],
+        mut f: impl FnMut(&[u8]),
+    ) -> Result<Option<usize>, ErrorKind> {
+        if self.current_record >= self.num_records {
+            return Ok(None);
+        }
+
+        let kind = self.record_kind;
+        let len = match &mut self.payload {
+            Payload::Compressed(c) => c.stream_record(f),
+            Payload::Lzss(l) => l.stream_record(window, f),
+            Payload::Numeric(n) => n.next_value().map(|val| {
+                val.map(|val| {
+                    let mut buf = [0_u8; numeric::MAX_RECORD_LEN];
+                    let record = numeric::record_bytes(kind, val, &mut buf);
+                    f(record);
+                    record.len()
+                })
+            }),
+            Payload::Raw(r) => r.next_record().map(|record| {
+                record.map(|record| {
+                    f(record);
+                    record.len()
+                })
+            }),
+        };
+        let len = len.map_err(|e| e.in_record(self.first_record + self.current_record))?;
+
+        if len.is_some() {
+            self.current_record += 1;
+        }
+        Ok(len)
+    }
+
+    /// Reads the next record from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * The record is longer than `dst`. `ErrorKind::BufferTooSmall` gives the length needed,
+    ///   which `next_record_len` also returns before decoding.
+    /// * Record length exceeds remaining file length.
+    pub fn next_record<'b>(&mut self, dst: &'b mut [u8]) -> Result<Option<&'b [u8]>, ErrorKind> {
+        if self.current_record >= self.num_records {
+            return Ok(None);
+        }
+
+        let kind = self.record_kind;
+        let position = self.payload.position();
+        let record = match &mut self.payload {
+            Payload::Compressed(c) => c.next_record(dst),
+            Payload::Lzss(l) => l.next_record(dst),
+            Payload::Numeric(n) => match n.next_value() {
+                Ok(Some(val)) => {
+                    let mut buf = [0_u8; numeric::MAX_RECORD_LEN];
+                    copy_record(numeric::record_bytes(kind, val, &mut buf), dst).map(Some)
+                }
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            },
+            Payload::Raw(r) => match r.next_record() {
+                Ok(Some(record)) => copy_record(record, dst).map(Some),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            },
+        };
+
+        // Raw and numeric records have already been moved past by the time we find they don't
+        // fit.
+        let record = record.map_err(|e| {
+            self.payload.set_position(position);
+            e.in_record(self.first_record + self.current_record)
+        })?;
+
+        if record.is_some() {
+            self.current_record += 1;
+        }
+        Ok(record)
+    }
+}
+
+// Copies `record` to the start of `dst`.
+fn copy_record<'b>(record: &[u8], dst: &'b mut [u8]) -> Result<&'b [u8], ErrorKind> {
+    let written_buf = dst
+        .get_mut(..record.len())
+        .ok_or(ErrorKind::BufferTooSmall {
+            record: 0,
+            needed: record.len(),
+        })?;
+    written_buf.copy_from_slice(record);
+    Ok(written_buf)
+}
+
+#[derive(Clone)]
+pub struct Raw<'a> {
+    num_records: usize,
+    records: &'a [u8],
+    current_record: usize,
+    layout: Layout,
+}
+
+impl<'a> Raw<'a> {
+    fn open<T: AsRef<[u8]> + ?Sized + 'a>(data: &'a T, layout: Layout) -> Result<Self, ErrorKind> {
+        let data = data.as_ref();
+        let (num_records, records) = layout.decode(data).ok_or(ErrorKind::LengthDecode)?;
+
+        Ok(Self {
+            num_records,
+            records,
+            current_record: 0,
+            layout,
+        })
+    }
+
+    #[must_use]
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.current_record
+    }
+
+    pub(crate) fn set_position(&mut self, position: usize) {
+        self.current_record = position;
+    }
+
+    pub(crate) fn end_position(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Reads the next record from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * Record length exceeds output buffer length.
+    /// * Record length exceeds remaining file length.
+    pub fn next_record(&mut self) -> Result<Option<&'a [u8]>, ErrorKind> {
+        let remaining_bytes = self
+            .records
+            .get(self.current_record..)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
+        if remaining_bytes.is_empty() {
+            return Ok(None);
+        }
+
+        let truncated = || ErrorKind::RecordTruncated {
+            record: 0,
+            offset: self.current_record,
+        };
+        let (len, remaining_bytes) = self.layout.decode(remaining_bytes).ok_or_else(truncated)?;
+        let record = remaining_bytes.get(..len).ok_or_else(truncated)?;
+
+        let len_dif =
+            self.records.len() - self.current_record - (remaining_bytes.len() - record.len());
+        self.current_record += len_dif;
+
+        Ok(Some(record))
+    }
+}
+
+// Bytes needed to hold an integer record, given as it's stored.
+fn integer_len(kind: RecordKind, val: u64) -> usize {
+    let bits = match kind {
+        RecordKind::Signed => {
+            // One more than the bits below the sign, for the sign itself.
+            let val = zigzag_decode(val);
+            let magnitude = if val < 0 { !val } else { val };
+            i64::BITS + 1 - magnitude.leading_zeros()
+        }
+        _ => u64::BITS - val.leading_zeros(),
+    };
+    bits.div_ceil(8).max(1) as usize
+}
+
+// What a writer opened from an existing archive can reuse from it.
+#[cfg(any(feature = "std", test))]
+enum Base {
+    // The archive was stored raw, so there's nothing to be gained from compressing it.
+    Raw,
+    // The archive's dictionary, in id order.
+    Dictionary(Vec<Vec<u8>>),
+}
+
+#[cfg(any(feature = "std", test))]
+#[derive(Default)]
+pub struct Writer<'a> {
+    raw_records: Vec<Cow<'a, [u8]>>,
+    compressor: Compress,
+    record_index: bool,
+    record_kind: RecordKind,
+    // Name and first record of each section.
+    sections: Vec<(Vec<u8>, usize)>,
+    shared_dict: Option<&'a SharedDictionary>,
+    // Only present if the writer was opened from an archive by `from_reader`.
+    base: Option<Base>,
+}
+
+#[cfg(any(feature = "std", test))]
+impl<'a> Writer<'a> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_options(CompressOptions::new())
+    }
+
+    #[must_use]
+    pub fn with_options(options: CompressOptions) -> Self {
+        Self {
+            raw_records: Vec::new(),
+            compressor: Compress::with_options(options),
+            record_index: false,
+            record_kind: RecordKind::Bytes,
+            sections: Vec::new(),
+            shared_dict: None,
+            base: None,
+        }
+    }
+
+    /// Starts from the records, sections and record index of the archive `reader` was opened
+    /// from, so that records can be added to or replaced before writing it back. Records added
+    /// from now on belong to the last section.
+    ///
+    /// Raw archives are written back raw, without trying to compress them. Archives stored with
+    /// the dictionary codec, Huffman coded or not, are compressed against their existing
+    /// dictionary, as long as it covers every record. Either can be undone with `recompress`.
+    ///
+    /// Any other archive has every codec tried on it again, as `write` otherwise does. That
+    /// includes archives compressed against a shared dictionary, which only try it again if it's
+    /// passed to `use_shared_dictionary`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading a record fails.
+    pub fn from_reader(reader: &Reader<'a>, options: CompressOptions) -> Result<Self, ErrorKind> {
+        let mut writer = Self::with_options(options);
+        writer.record_index = reader.has_record_index();
+        writer.record_kind = reader.record_kind;
+        if let Some(table) = reader.sections {
+            for (name, first, _) in table.iter() {
+                writer.sections.push((name.to_vec(), first));
+            }
+        }
+
+        let mut payload = reader.payload.clone();
+        payload.set_position(0);
+        match &mut payload {
+            // Raw records can be borrowed from the archive rather than copied.
+            Payload::Raw(r) => {
+                while let Some(record) = r.next_record()? {
+                    writer.push_record(Cow::Borrowed(record));
+                }
+            }
+            Payload::Compressed(c) => {
+                let mut record = Vec::new();
+                while c
+                    .stream_record(|piece| record.extend_from_slice(piece))?
+                    .is_some()
+                {
+                    writer.push_record(Cow::Owned(std::mem::take(&mut record)));
+                }
+            }
+            Payload::Lzss(l) => {
+                // LZSS records store their length, so each can be decoded in one go rather than
+                // streamed.
+                while let Some(len) = l.record_len()? {
+                    let mut record = vec![0_u8; len];
+                    l.next_record(&mut record)?;
+                    writer.push_record(Cow::Owned(record));
+                }
+            }
+            Payload::Numeric(n) => {
+                let mut buf = [0_u8; numeric::MAX_RECORD_LEN];
+                while let Some(val) = n.next_value()? {
+                    let record = numeric::record_bytes(reader.record_kind, val, &mut buf);
+                    writer.push_record(Cow::Owned(record.to_vec()));
+                }
+            }
+        }
+
+        writer.base = match (reader.codec, &payload) {
+            (RAW, _) => Some(Base::Raw),
+            (COMPRESSED | HUFFMAN, Payload::Compressed(c)) => {
+                let entries = c.dict_entries().map(|entry| entry.map(<[u8]>::to_vec));
+                Some(Base::Dictionary(entries.collect::<Result<_, _>>()?))
+            }
+            _ => None,
+        };
+
+        Ok(writer)
+    }
+
+    /// Tries every codec on the records when writing, instead of reusing the codec or dictionary
+    /// of the archive passed to `from_reader`.
+    pub fn recompress(&mut self) {
+        self.base = None;
+    }
+
+    /// Also tries compressing the records against `dict`, in which case the archive refers to
+    /// `dict` by its id instead of storing a dictionary of its own. Such archives need to be
+    /// read with `Reader::open_with_dict`.
+    pub fn use_shared_dictionary(&mut self, dict: &'a SharedDictionary) {
+        self.shared_dict = Some(dict);
+    }
+
+    /// What kind of records have been added so far.
+    #[must_use]
+    pub fn record_kind(&self) -> RecordKind {
+        self.record_kind
+    }
+
+    /// The records added so far, as they're stored. Integers are LEB128 encoded.
+    pub fn records(&self) -> impl Iterator<Item = &[u8]> {
+        self.raw_records.iter().map(AsRef::as_ref)
+    }
+
+    /// Stores the offset of each record in the archive, allowing `Reader::seek` and
+    /// `Reader::record` to jump straight to a record instead of scanning for it.
+    pub fn emit_record_index(&mut self) {
+        self.record_index = true;
+    }
+
+    /// Starts a new section, read back with `Reader::section`. Records added from now on belong
+    /// to it, until the next call.
+    ///
+    /// Records added before the first section is started are put in an unnamed section of their
+    /// own.
+    pub fn begin_section<T: AsRef<[u8]> + ?Sized>(&mut self, name: &T) {
+        if self.sections.is_empty() && !self.raw_records.is_empty() {
+            self.sections.push((Vec::new(), 0));
+        }
+
+        self.sections
+            .push((name.as_ref().to_vec(), self.raw_records.len()));
+    }
+
+    /// Same as passing `CompressOptions::level` to `with_options`. Defaults to
+    /// `CompressionLevel::Greedy`.
+    pub fn set_compression_level(&mut self, level: CompressionLevel) {
+        self.compressor.set_level(level);
+    }
+
+    pub fn preload_dict<T: AsRef<[u8]> + ?Sized + 'a>(&mut self, entries: &[&T]) {
+        for e in entries {
+            self.compressor.add_dictionary_entry(e);
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if integer records have already been added.
+    pub fn add_record<T: AsRef<[u8]> + ?Sized + 'a>(&mut self, record: &'a T) {
+        self.set_record_kind(RecordKind::Bytes);
+        self.push_record(Cow::Borrowed(record.as_ref()));
+    }
+
+    /// Replaces record `n`, which keeps its place in whichever section it belongs to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `n` records or fewer, or if the records are integers.
+    pub fn replace_record<T: AsRef<[u8]> + ?Sized + 'a>(&mut self, n: usize, record: &'a T) {
+        self.check_replacement(n, RecordKind::Bytes);
+        self.compressor.replace_record(n, record);
+        self.raw_records[n] = Cow::Borrowed(record.as_ref());
+    }
+
+    /// Adds an unsigned integer, to be read back with `Reader::next_u64`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if other kinds of record have already been added.
+    pub fn add_u64(&mut self, val: u64) {
+        self.set_record_kind(RecordKind::Unsigned);
+        self.add_integer(val);
+    }
+
+    /// Adds a signed integer, to be read back with `Reader::next_i64`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if other kinds of record have already been added.
+    pub fn add_i64(&mut self, val: i64) {
+        self.set_record_kind(RecordKind::Signed);
+        self.add_integer(zigzag_encode(val));
+    }
+
+    /// Replaces record `n` with an unsigned integer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `n` records or fewer, or if they aren't unsigned integers.
+    pub fn replace_u64(&mut self, n: usize, val: u64) {
+        self.check_replacement(n, RecordKind::Unsigned);
+        self.replace_integer(n, val);
+    }
+
+    /// Replaces record `n` with a signed integer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `n` records or fewer, or if they aren't signed integers.
+    pub fn replace_i64(&mut self, n: usize, val: i64) {
+        self.check_replacement(n, RecordKind::Signed);
+        self.replace_integer(n, zigzag_encode(val));
+    }
+
+    fn add_integer(&mut self, val: u64) {
+        let record = Leb128::encode(val);
+        self.push_record(Cow::Owned(record.as_ref().to_vec()));
+    }
+
+    fn replace_integer(&mut self, n: usize, val: u64) {
+        let record = Leb128::encode(val);
+        self.compressor.replace_record(n, record);
+        self.raw_records[n] = Cow::Owned(record.as_ref().to_vec());
+    }
+
+    fn push_record(&mut self, record: Cow<'a, [u8]>) {
+        self.compressor.add_record(&record);
+        self.raw_records.push(record);
+    }
+
+    fn check_replacement(&self, n: usize, kind: RecordKind) {
+        assert!(n < self.raw_records.len(), "No record {} to replace", n);
+        assert_eq!(
+            self.record_kind, kind,
+            "Can't mix record kinds in one archive"
+        );
+    }
+
+    // Every record as an integer, or `None` if any of them isn't one.
+    fn numeric_values(&self) -> Option<Vec<i64>> {
+        self.raw_records
+            .iter()
+            .map(|record| match self.record_kind {
+                RecordKind::Bytes => {
+                    let val: i64 = std::str::from_utf8(record).ok()?.parse().ok()?;
+                    // Reading it back has to give the same text, so no "+1" or "007".
+                    Some(val).filter(|val| val.to_string().as_bytes() == record.as_ref())
+                }
+                RecordKind::Unsigned => i64::try_from(Leb128::decode(record)?.0).ok(),
+                RecordKind::Signed => Some(zigzag_decode(Leb128::decode(record)?.0)),
+            })
+            .collect()
+    }
+
+    fn set_record_kind(&mut self, kind: RecordKind) {
+        if self.raw_records.is_empty() {
+            self.record_kind = kind;
+        }
+
+        assert_eq!(
+            self.record_kind, kind,
+            "Can't mix record kinds in one archive"
+        );
+    }
+
+    fn limits(&self) -> ArchiveLimits {
+        let max_record_len = self.records().map(<[u8]>::len).max().unwrap_or(0);
+        let max_decoded_len = match self.record_kind {
+            RecordKind::Bytes => max_record_len,
+            kind => self
+                .records()
+                .filter_map(Leb128::decode)
+                .map(|(val, _)| integer_len(kind, val))
+                .max()
+                .unwrap_or(0),
+        };
+
+        ArchiveLimits {
+            num_records: self.raw_records.len(),
+            max_record_len,
+            max_decoded_len,
+        }
+    }
+
+    // Stores the records with every codec, returning whichever gives the smallest payload.
+    fn smallest_payload(&self, raw: Option<Vec<u8>>) -> (u8, Vec<u8>) {
+        let lzss = lzss::store_archive(self.raw_records.iter().map(AsRef::as_ref));
+
+        // Building a dictionary is the slow part, so an existing one is used if it can be.
+        let existing = match &self.base {
+            Some(Base::Dictionary(entries)) => self.compressor.store_archives_with_dict(entries),
+            _ => None,
+        };
+        let (compressed, huffman) = existing.unwrap_or_else(|| self.compressor.store_archives());
+        let numeric = self
+            .numeric_values()
+            .map(|values| (NUMERIC, numeric::store_archive(&values)));
+        let shared = self.shared_dict.and_then(|dict| {
+            let archive = self.compressor.store_shared_archive(dict)?;
+            Some((SHARED_DICT, archive))
+        });
+
+        // Whichever is smallest, preferring the plain dictionary codec if there's a tie.
+        let mut best = (COMPRESSED, compressed);
+        let candidates = huffman.map(|huffman| (HUFFMAN, huffman));
+        let candidates = candidates
+            .into_iter()
+            .chain(Some((LZSS, lzss)))
+            .chain(numeric)
+            .chain(shared)
+            .chain(raw.map(|raw| (RAW, raw)));
+        for candidate in candidates {
+            if candidate.1.len() < best.1.len() {
+                best = candidate;
+            }
+        }
+        best
+    }
+
+    /// # Errors
+    ///
+    /// Returns error on failure to write to the `writer`.
+    pub fn write(&self, writer: impl std::io::Write) -> Result<(), ErrorKind> {
+        let mut raw = Vec::with_capacity(self.raw_records.iter().map(|r| r.len() + 2).sum());
+        raw.extend_from_slice(Leb128::encode_usize(self.raw_records.len()).as_ref());
+
+        for r in &self.raw_records {
+            raw.extend_from_slice(Leb128::encode_usize(r.len()).as_ref());
+            raw.extend_from_slice(r);
+        }
+
+        let raw = Some(raw).filter(|_| self.compressor.options().raw_allowed());
+        let (codec, payload) = match (&self.base, raw) {
+            (Some(Base::Raw), Some(raw)) => (RAW, raw),
+            (_, raw) => self.smallest_payload(raw),
+        };
+        self.write_payload(writer, codec, &payload)
+    }
+
+    // Writes the archive around a payload of the records stored with `codec`.
+    fn write_payload(
+        &self,
+        mut writer: impl std::io::Write,
+        codec: u8,
+        payload: &[u8],
+    ) -> Result<(), ErrorKind> {
+        use std::io::Write;
+
+        let mut flags = 0;
+        if self.record_index {
+            flags |= FLAG_RECORD_INDEX;
+        }
+        if self.record_kind != RecordKind::Bytes {
+            flags |= FLAG_RECORD_KIND;
+        }
+        if !self.sections.is_empty() {
+            flags |= FLAG_SECTIONS;
+        }
+
+        // The archive is built up in memory first so that the checksum can be appended.
+        let mut archive = Vec::new();
+        archive.write_all(&MAGIC)?;
+        archive.write_all(&[FORMAT_VERSION, codec, flags])?;
+
+        let limits = self.limits();
+        archive.write_all(Leb128::encode_usize(limits.num_records).as_ref())?;
+        archive.write_all(Leb128::encode_usize(limits.max_record_len).as_ref())?;
+        archive.write_all(Leb128::encode_usize(limits.max_decoded_len).as_ref())?;
+
+        if self.record_index {
+            // Rather than have each codec track where its records start, we just walk the
+            // payload we're about to write.
+            let dict = self.shared_dict.map(SharedDictionary::store);
+            let mut reader = Payload::open(codec, payload, Layout::Wide, dict.as_deref())?;
+            let mut offsets = Vec::with_capacity(reader.num_records());
+            for _ in 0..reader.num_records() {
+                offsets.push(reader.position());
+                reader.skip_record()?;
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let width = addr_width(offsets.last().copied().unwrap_or(0)) as u8;
+            archive.write_all(Leb128::encode_usize(offsets.len()).as_ref())?;
+            archive.write_all(&[width])?;
+            for offset in offsets {
+                write_addr(&mut archive, offset, width.into());
+            }
+        }
+
+        if self.record_kind != RecordKind::Bytes {
+            archive.write_all(&[self.record_kind.tag()])?;
+        }
+
+        if !self.sections.is_empty() {
+            archive.write_all(Leb128::encode_usize(self.sections.len()).as_ref())?;
+
+            let ends = self.sections.iter().skip(1).map(|&(_, first)| first);
+            let ends = ends.chain(core::iter::once(self.raw_records.len()));
+            for ((name, first), end) in self.sections.iter().zip(ends) {
+                archive.write_all(Leb128::encode_usize(name.len()).as_ref())?;
+                archive.write_all(name)?;
+                archive.write_all(Leb128::encode_usize(end - first).as_ref())?;
+            }
+        }
+
+        archive.write_all(payload)?;
+
+        writer.write_all(&archive)?;
+        writer.write_all(&crc32(&archive).to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Where the optional blocks start, after the header and limits.
+    fn blocks_start(output: &[u8]) -> usize {
+        ArchiveLimits::decode(output, HEADER_LEN).unwrap().1
+    }
+
+    // Recomputes the trailing checksum after a test has patched `archive`, so that it's the
+    // patched bytes that get rejected rather than the checksum.
+    fn with_fixed_checksum(archive: &mut [u8]) {
+        let checksum_start = archive.len() - CHECKSUM_LEN;
+        let checksum = crc32(&archive[..checksum_start]).to_le_bytes();
+        archive[checksum_start..].copy_from_slice(&checksum);
+    }
+
+    #[test]
+    fn round_trip_compressed() {
+        // Expects a file that does compress. The input from AoC 2020 day 7 is an example.
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let mut writer = Writer::with_options(CompressOptions::new().allow_huffman(false));
+
+        let mut num_records = 0;
+        for line in input_text.lines() {
+            writer.add_record(line);
+            num_records += 1;
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        assert_eq!(COMPRESSED, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(num_records, reader.num_records());
+
+        let mut buf = [0_u8; 512];
+        for (i, line) in input_text.lines().enumerate() {
+            let record = reader.next_record(&mut buf).unwrap();
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
+        }
+    }
+
+    #[test]
+    fn round_trip_raw() {
+        // Expects a file that doesn't compress. The input from AoC 2020 day 2 is an example.
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let mut writer = Writer::with_options(CompressOptions::new().allow_huffman(false));
+
+        let mut num_records = 0;
+        for line in input_text.lines() {
+            writer.add_record(line);
+            num_records += 1;
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        assert_eq!(RAW, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(num_records, reader.num_records());
+
+        let mut buf = [0_u8; 512];
+        for (i, line) in input_text.lines().enumerate() {
+            let record = reader.next_record(&mut buf).unwrap();
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
+        }
+    }
+
+    #[test]
+    fn round_trip_huffman() {
+        // Both compress further once the ids are Huffman coded, even the one which otherwise
+        // doesn't compress at all.
+        for path in &["test_data/aoc_2007.txt", "test_data/aoc_2002.txt"] {
+            let input_text = std::fs::read_to_string(path).unwrap();
+            let mut writer = Writer::new();
+            for line in input_text.lines() {
+                writer.add_record(line);
+            }
+
+            let mut output: Vec<u8> = Vec::new();
+            writer.write(&mut output).unwrap();
+            assert_eq!(HUFFMAN, output[CODEC_ADDR], "{path}");
+
+            let mut reader = Reader::open(&output).unwrap();
+            let mut buf = [0_u8; 512];
+            for (i, line) in input_text.lines().enumerate() {
+                let record = reader.next_record(&mut buf).unwrap();
+                assert_eq!(record, Some(line.as_bytes()), "{path} {i}: {line}");
+            }
+            assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+        }
+    }
+
+    // Records of random bytes, which none of the codecs can compress.
+    fn incompressible_records() -> Vec<Vec<u8>> {
+        let mut state = 0x2545_F491_u32;
+        (0..500)
+            .map(|i| {
+                (0..i % 40)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 17;
+                        state ^= state << 5;
+                        state.to_le_bytes()[0]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_incompressible() {
+        let records = incompressible_records();
+        let mut writer = Writer::new();
+        for record in &records {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(RAW, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 512];
+        for (i, record) in records.iter().enumerate() {
+            let expected = Some(&record[..]);
+            assert_eq!(reader.next_record(&mut buf).unwrap(), expected, "{i}");
+        }
+    }
+
+    #[test]
+    fn without_raw_fallback() {
+        let records = incompressible_records();
+        let mut writer = Writer::with_options(CompressOptions::new().allow_raw(false));
+        for record in &records {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_ne!(RAW, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 512];
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(
+                reader.next_record(&mut buf).unwrap(),
+                Some(&record[..]),
+                "{i}"
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_lzss() {
+        // Long records repeating themselves from far back, which only LZSS can make use of.
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+        let records: Vec<String> = lines
+            .chunks(20)
+            .map(|chunk| chunk.concat().repeat(2))
+            .collect();
+
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        for record in &records {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(LZSS, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(records.len(), reader.num_records());
+
+        let mut buf = [0_u8; 4096];
+        for (i, record) in records.iter().enumerate().rev() {
+            assert_eq!(
+                reader.record(i, &mut buf).unwrap(),
+                Some(record.as_bytes()),
+                "{i}"
+            );
+        }
+
+        reader.seek(0).unwrap();
+        for record in &records {
+            let mut streamed = Vec::new();
+            let len = reader.stream_record(|piece| streamed.extend_from_slice(piece));
+            assert_eq!(len.unwrap(), Some(record.len()));
+            assert_eq!(streamed, record.as_bytes());
+        }
+    }
+
+    #[test]
+    fn next_record_lens() {
+        let text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let lzss_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let lzss_lines: Vec<_> = lzss_text.lines().collect();
+        let lzss_records: Vec<String> = lzss_lines
+            .chunks(20)
+            .map(|chunk| chunk.concat().repeat(2))
+            .collect();
+        let numbers: Vec<String> = (0..200).map(|i| (i * 37 - 900).to_string()).collect();
+
+        let inputs: [(u8, Vec<&str>); 4] = [
+            (RAW, vec!["ab", "", "cde"]),
+            (LZSS, lzss_records.iter().map(String::as_str).collect()),
+            (NUMERIC, numbers.iter().map(String::as_str).collect()),
+            (COMPRESSED, text.lines().collect()),
+        ];
+        for (codec, records) in &inputs {
+            let mut writer = Writer::new();
+            for record in records {
+                writer.add_record(*record);
+            }
+            let mut output: Vec<u8> = Vec::new();
+            writer.write(&mut output).unwrap();
+            if *codec == COMPRESSED {
+                assert!(matches!(output[CODEC_ADDR], COMPRESSED | HUFFMAN));
+            } else {
+                assert_eq!(*codec, output[CODEC_ADDR]);
+            }
+
+            let mut reader = Reader::open(&output).unwrap();
+            let mut buf = vec![0_u8; 4096];
+            for (i, record) in records.iter().enumerate() {
+                let len = reader.next_record_len().unwrap().unwrap();
+                assert_eq!(len, record.len(), "{codec} {i}");
+
+                if len > 0 {
+                    let needed = match reader.next_record(&mut buf[..len - 1]) {
+                        Err(ErrorKind::BufferTooSmall { record, needed }) if record == i => {
+                            Some(needed)
+                        }
+                        _ => None,
+                    };
+                    assert_eq!(needed, Some(len), "{codec} {i}");
+                }
+                assert_eq!(
+                    reader.next_record(&mut buf[..len]).unwrap(),
+                    Some(record.as_bytes())
+                );
+            }
+            assert_eq!(reader.next_record_len().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let numbers: Vec<String> = (0..200).map(|i| (i * 37 - 900).to_string()).collect();
+        for records in &[
+            text.lines().collect::<Vec<_>>(),
+            numbers.iter().map(String::as_str).collect(),
+        ] {
+            let mut writer = Writer::new();
+            writer.emit_record_index();
+            writer.begin_section("first");
+            writer.add_record(records[0]);
+            writer.begin_section("rest");
+            for record in &records[1..] {
+                writer.add_record(*record);
+            }
+            let mut output: Vec<u8> = Vec::new();
+            writer.write(&mut output).unwrap();
+
+            let reader = Reader::open(&output).unwrap();
+            reader.validate().unwrap();
+            reader.section(1).unwrap().unwrap().validate().unwrap();
+        }
+
+        for input in &[V3_COMPRESSED, V3_RAW, V4_SECTIONS] {
+            Reader::open(input).unwrap().validate().unwrap();
+        }
+
+        let mut writer = Writer::new();
+        writer.add_record("ab");
+        writer.add_record("cde");
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(RAW, output[CODEC_ADDR]);
+        let records_start = blocks_start(&output);
+
+        // A byte after the last record.
+        let mut trailing = output.clone();
+        trailing.insert(trailing.len() - CHECKSUM_LEN, 0);
+        with_fixed_checksum(&mut trailing);
+        assert!(matches!(
+            Reader::open(&trailing).unwrap().validate(),
+            Err(ErrorKind::RecordsOutOfRange)
+        ));
+
+        // Claiming a record which isn't there.
+        let mut missing = output.clone();
+        missing[HEADER_LEN] += 1;
+        missing[records_start] += 1;
+        with_fixed_checksum(&mut missing);
+        assert!(matches!(
+            Reader::open(&missing).unwrap().validate(),
+            Err(ErrorKind::RecordsOutOfRange)
+        ));
+
+        // Limits which don't match the records.
+        let mut limits = output;
+        limits[HEADER_LEN + 1] -= 1;
+        with_fixed_checksum(&mut limits);
+        assert!(matches!(
+            Reader::open(&limits).unwrap().validate(),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn validate_corrupt_archive() {
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        for _ in 0..3 {
+            writer.add_record("TOBEORNOTTOBEORTOBEORNOT");
+        }
+        writer.add_record("TOBE");
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(matches!(output[CODEC_ADDR], COMPRESSED | HUFFMAN));
+
+        // Anything which passes validation has to read back without errors.
+        let mut buf = [0_u8; 64];
+        for idx in HEADER_LEN..output.len() - CHECKSUM_LEN {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[idx] = val;
+                with_fixed_checksum(&mut corrupt);
+
+                let Ok(mut reader) = Reader::open(&corrupt) else {
+                    continue;
+                };
+                if reader.validate().is_err() {
+                    continue;
+                }
+
+                while reader.next_record(&mut buf).unwrap().is_some() {}
+                for i in 0..reader.num_records() {
+                    assert!(reader.record(i, &mut buf).unwrap().is_some());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_numeric() {
+        // Steadily rising values, stored as text.
+        let records: Vec<String> = (0..500_i64)
+            .map(|i| (i * i % 97 - 40 + i * 200).to_string())
+            .collect();
+
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        writer.add_record("-50");
+        writer.begin_section("rising");
+        for record in &records {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(NUMERIC, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(records.len() + 1, reader.num_records());
+        assert_eq!(reader.record(0, &mut [0; 3]).unwrap(), Some(&b"-50"[..]));
+        assert!(matches!(
+            reader.clone().record(0, &mut [0; 2]),
+            Err(ErrorKind::BufferTooSmall {
+                record: 0,
+                needed: 3
+            })
+        ));
+
+        let mut section = reader.section_named("rising").unwrap().unwrap();
+        let mut buf = [0_u8; 20];
+        for (i, record) in records.iter().enumerate().rev() {
+            assert_eq!(
+                section.record(i, &mut buf).unwrap(),
+                Some(record.as_bytes()),
+                "{i}"
+            );
+        }
+
+        section.seek(0).unwrap();
+        for record in &records {
+            let mut streamed = Vec::new();
+            let len = section.stream_record(|piece| streamed.extend_from_slice(piece));
+            assert_eq!(len.unwrap(), Some(record.len()));
+            assert_eq!(streamed, record.as_bytes());
+        }
+        assert_eq!(section.next_record(&mut buf).unwrap(), None);
+
+        // The values are parsed straight from the integers, without needing a buffer.
+        section.seek(0).unwrap();
+        for record in &records {
+            assert_eq!(
+                section.next_parsed(&mut []).unwrap(),
+                record.parse::<i64>().ok()
+            );
+        }
+        assert_eq!(section.next_parsed::<i64>(&mut []).unwrap(), None);
+        section.seek(0).unwrap();
+        assert!(matches!(
+            section.next_parsed::<u64>(&mut []),
+            Err(ErrorKind::InvalidField { .. })
+        ));
+        section.seek(1).unwrap();
+        assert_eq!(section.next_parsed::<u64>(&mut []).unwrap(), Some(161));
+        assert_eq!(section.next_parsed::<i16>(&mut []).unwrap(), Some(364));
+        // Parsed as text if the type can't take an integer.
+        assert_eq!(
+            section.next_parsed::<(u8, u16)>(&mut []).unwrap(),
+            Some((b'5', 69))
+        );
+
+        // Text that wouldn't read back the same isn't stored as a number.
+        for text in &["007", "+1", "1.0", "99999999999999999999"] {
+            let mut writer = Writer::new();
+            writer.add_record("12");
+            writer.add_record(*text);
+            assert!(writer.numeric_values().is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn numeric_text_with_any_codec() {
+        let records = ["12", "-3", "300", "7"];
+        let mut writer = Writer::new();
+        for record in &records {
+            writer.add_record(*record);
+        }
+
+        let values = writer.numeric_values().unwrap();
+        let payloads = [
+            (NUMERIC, numeric::store_archive(&values)),
+            (
+                LZSS,
+                lzss::store_archive(records.iter().map(|r| r.as_bytes())),
+            ),
+        ];
+        for (codec, payload) in &payloads {
+            let mut output: Vec<u8> = Vec::new();
+            writer.write_payload(&mut output, *codec, payload).unwrap();
+            assert_eq!(*codec, output[CODEC_ADDR]);
+
+            // Reads the same, whichever codec the records were stored with.
+            let mut reader = Reader::open(&output).unwrap();
+            assert!(matches!(reader.next_i64(), Err(ErrorKind::WrongRecordKind)));
+            assert!(matches!(reader.next_u64(), Err(ErrorKind::WrongRecordKind)));
+
+            let mut buf = [0_u8; 3];
+            for record in &records {
+                let val = reader.next_parsed::<i64>(&mut buf).unwrap();
+                assert_eq!(val, record.parse().ok(), "{codec}: {record}");
+            }
+            assert_eq!(reader.next_parsed::<i64>(&mut buf).unwrap(), None);
+
+            reader.seek(1).unwrap();
+            assert!(matches!(
+                reader.next_parsed::<u64>(&mut buf),
+                Err(ErrorKind::InvalidField { record: 1, .. })
+            ));
+            let mut buf = [0_u8; 8];
+            assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&b"300"[..]));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut writer = Writer::new();
+        writer.add_record("Hello World!");
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(Reader::open(&output).is_ok());
+
+        let mut bad_magic = output.clone();
+        bad_magic[MAGIC_ADDR.start] = b'X';
+        assert!(matches!(
+            Reader::open(&bad_magic),
+            Err(ErrorKind::InvalidMagic)
+        ));
+        assert!(matches!(
+            Reader::open(&output[..HEADER_LEN - 1]),
+            Err(ErrorKind::InvalidMagic)
+        ));
+
+        let mut bad_version = output.clone();
+        bad_version[VERSION_ADDR] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            Reader::open(&bad_version),
+            Err(ErrorKind::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+
+        let mut bad_codec = output;
+        bad_codec[CODEC_ADDR] = 0xFF;
+        with_fixed_checksum(&mut bad_codec);
+        assert!(matches!(
+            Reader::open(&bad_codec),
+            Err(ErrorKind::UnknownCodec(0xFF))
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_archive() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let mut writer = Writer::new();
+        for line in input_text.lines() {
+            writer.add_record(line);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(Reader::open(&output).is_ok());
+
+        // Flip a byte in the payload.
+        let mut flipped = output.clone();
+        flipped[output.len() / 2] ^= 0x10;
+        assert!(matches!(
+            Reader::open(&flipped),
+            Err(ErrorKind::ChecksumMismatch)
+        ));
+
+        // Truncated archive.
+        assert!(matches!(
+            Reader::open(&output[..output.len() - 1]),
+            Err(ErrorKind::ChecksumMismatch)
+        ));
+        assert!(matches!(
+            Reader::open(&output[..HEADER_LEN]),
+            Err(ErrorKind::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn record_errors() {
+        let mut writer = Writer::new();
+        for record in &["ab", "cde", "fg"] {
+            writer.add_record(*record);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(RAW, output[CODEC_ADDR]);
+
+        // Make the last record longer than what's left of the archive.
+        let records_start = blocks_start(&output) + 1;
+        output[records_start + 7] = 5;
+        with_fixed_checksum(&mut output);
+
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 8];
+        assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&b"ab"[..]));
+
+        let err = reader.next_record(&mut buf[..2]).unwrap_err();
+        assert!(matches!(
+            err,
+            ErrorKind::BufferTooSmall {
+                record: 1,
+                needed: 3
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "buffer is too small for record 1, which needs 3 bytes"
+        );
+
+        // The record is still there to be read with a bigger buffer.
+        assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&b"cde"[..]));
+        assert!(matches!(
+            reader.next_record(&mut buf),
+            Err(ErrorKind::RecordTruncated {
+                record: 2,
+                offset: 7
+            })
+        ));
+    }
+
+    fn check_random_access(path: &str, record_index: bool) {
+        let input_text = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+
+        let mut writer = Writer::new();
+        if record_index {
+            writer.emit_record_index();
+        }
+        for line in &lines {
+            writer.add_record(*line);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(record_index, reader.has_record_index());
+
+        let mut buf = [0_u8; 512];
+        for (i, line) in lines.iter().enumerate().rev() {
+            let record = reader.record(i, &mut buf).unwrap();
+            assert_eq!(record, Some(line.as_bytes()), "{i}: {line}");
+        }
+
+        assert_eq!(reader.record(lines.len(), &mut buf).unwrap(), None);
+
+        reader.seek(3).unwrap();
+        assert_eq!(
+            reader.next_record(&mut buf).unwrap(),
+            Some(lines[3].as_bytes())
+        );
+        assert_eq!(
+            reader.next_record(&mut buf).unwrap(),
+            Some(lines[4].as_bytes())
+        );
+
+        reader.seek(lines.len() + 10).unwrap();
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn random_access() {
+        for &record_index in &[false, true] {
+            check_random_access("test_data/aoc_2007.txt", record_index);
+            check_random_access("test_data/aoc_2002.txt", record_index);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_index() {
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        writer.add_record("Hello");
+        writer.add_record("World!");
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(Reader::open(&output).is_ok());
+
+        // Claim there's an extra record in the index.
+        let index_start = blocks_start(&output);
+        output[index_start] += 1;
+        with_fixed_checksum(&mut output);
+        assert!(matches!(
+            Reader::open(&output),
+            Err(ErrorKind::IndexOutOfRange)
+        ));
+    }
+
+    fn check_stream(records: &[&str]) {
+        let mut writer = Writer::new();
+        for record in records {
+            writer.add_record(*record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        for record in records {
+            let mut streamed = Vec::new();
+            let len = reader
+                .stream_record(|chunk| streamed.extend_from_slice(chunk))
+                .unwrap();
+            assert_eq!(len, Some(record.len()));
+            assert_eq!(streamed, record.as_bytes());
+        }
+
+        assert_eq!(reader.stream_record(|_| panic!()).unwrap(), None);
+
+        let mut window = [0_u8; 64];
+        let mut reader = Reader::open(&output).unwrap();
+        for record in records {
+            let mut streamed = Vec::new();
+            let len = reader
+                .stream_record_with_window(&mut window, |chunk| streamed.extend_from_slice(chunk))
+                .unwrap();
+            assert_eq!(len, Some(record.len()));
+            assert_eq!(streamed, record.as_bytes());
+        }
+    }
+
+    #[test]
+    fn stream_records() {
+        // A single long record, similar to the day 8 and day 16 inputs.
+        let digits: String = (0..15_000)
+            .map(|i| ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"][i * 7 % 13 % 10])
+            .collect();
+        check_stream(&[&digits, "end"]);
+
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        check_stream(&input_text.lines().collect::<Vec<_>>());
+    }
+
+    // Written by format version 3, with a record index.
+    const V3_COMPRESSED: &[u8] = &[
+        65, 79, 67, 65, 3, 1, 1, 9, 0, 0, 0, 2, 0, 4, 0, 6, 0, 8, 0, 10, 0, 12, 0, 14, 0, 16, 0,
+        10, 0, 27, 0, 9, 0, 0, 0, 11, 0, 9, 0, 84, 79, 66, 69, 79, 82, 78, 79, 84, 4, 0, 84, 79,
+        66, 69, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 0, 123, 209, 5, 103,
+    ];
+    const V3_RAW: &[u8] = &[
+        65, 79, 67, 65, 3, 0, 1, 7, 0, 0, 0, 8, 0, 16, 0, 24, 0, 32, 0, 42, 0, 50, 0, 7, 7, 49, 44,
+        48, 44, 48, 44, 51, 7, 49, 44, 49, 44, 50, 44, 51, 7, 49, 44, 51, 44, 52, 44, 51, 7, 49,
+        44, 53, 44, 48, 44, 51, 9, 50, 44, 49, 44, 49, 48, 44, 49, 57, 7, 49, 44, 48, 44, 48, 44,
+        51, 8, 57, 57, 44, 48, 44, 48, 44, 51, 234, 102, 59, 38,
+    ];
+
+    #[test]
+    fn reads_version_3() {
+        let compressed_records = [
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBEORNOT",
+            "TOBE",
+            "TOBEORNOT",
+        ];
+        let raw_records = [
+            "1,0,0,3",
+            "1,1,2,3",
+            "1,3,4,3",
+            "1,5,0,3",
+            "2,1,10,19",
+            "1,0,0,3",
+            "99,0,0,3",
+        ];
+
+        for (input, records) in [
+            (V3_COMPRESSED, &compressed_records[..]),
+            (V3_RAW, &raw_records[..]),
+        ] {
+            let mut reader = Reader::open(input).unwrap();
+            assert_eq!(records.len(), reader.num_records());
+            assert!(reader.has_record_index());
+
+            let mut buf = [0_u8; 16];
+            for (i, record) in records.iter().enumerate().rev() {
+                assert_eq!(reader.record(i, &mut buf).unwrap(), Some(record.as_bytes()));
+            }
+
+            reader.seek(0).unwrap();
+            for record in records {
+                assert_eq!(
+                    reader.next_record(&mut buf).unwrap(),
+                    Some(record.as_bytes())
+                );
+            }
+            assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+        }
+    }
+
+    // Written by format version 4, with a record index and two sections of signed integers.
+    const V4_SECTIONS: &[u8] = &[
+        65, 79, 67, 65, 4, 4, 7, 3, 1, 0, 1, 2, 2, 2, 1, 97, 2, 1, 98, 1, 3, 0, 5, 9, 0, 75, 193,
+        64, 31, 47, 193, 218,
+    ];
+
+    #[test]
+    fn reads_version_4() {
+        const LIMITS: Option<ArchiveLimits> = ArchiveLimits::read(V4_SECTIONS);
+        assert_eq!(LIMITS, None);
+
+        let mut reader = Reader::open(V4_SECTIONS).unwrap();
+        assert_eq!(reader.limits(), None);
+        assert!(reader.has_record_index());
+
+        let values: Vec<_> = reader.i64_records().map(Result::unwrap).collect();
+        assert_eq!(values, [-3, 300, 7]);
+        let mut second = reader.section_named("b").unwrap().unwrap();
+        assert_eq!(second.next_i64().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn limits() {
+        let mut writer = Writer::new();
+        writer.add_record("ab");
+        writer.add_record("");
+        writer.add_record("cdefg");
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let limits = ArchiveLimits {
+            num_records: 3,
+            max_record_len: 5,
+            max_decoded_len: 5,
+        };
+        assert_eq!(ArchiveLimits::read(&output), Some(limits));
+        assert_eq!(Reader::open(&output).unwrap().limits(), Some(limits));
+        assert_eq!(ArchiveLimits::read(&output[1..]), None);
+
+        // Integers are counted by the bytes needed to hold them, not their LEB128 length.
+        let unsigned = [(255, 1), (256, 2), (u64::from(u32::MAX), 4), (u64::MAX, 8)];
+        for &(val, len) in &unsigned {
+            let mut writer = Writer::new();
+            writer.add_u64(0);
+            writer.add_u64(val);
+            let mut output: Vec<u8> = Vec::new();
+            writer.write(&mut output).unwrap();
+
+            let limits = ArchiveLimits::read(&output).unwrap();
+            assert_eq!(limits.max_decoded_len, len, "{val}");
+            assert_eq!(limits.max_record_len, Leb128::encode(val).len);
+        }
+
+        let signed = [(127, 1), (-128, 1), (128, 2), (-129, 2), (i64::MIN, 8)];
+        for &(val, len) in &signed {
+            let mut writer = Writer::new();
+            writer.add_i64(val);
+            let mut output: Vec<u8> = Vec::new();
+            writer.write(&mut output).unwrap();
+
+            let limits = ArchiveLimits::read(&output).unwrap();
+            assert_eq!(limits.max_decoded_len, len, "{val}");
+        }
+
+        // The number of records has to match the payload.
+        let mut output: Vec<u8> = Vec::new();
+        Writer::new().write(&mut output).unwrap();
+        assert_eq!(ArchiveLimits::read(&output), Some(ArchiveLimits::default()));
+        output[HEADER_LEN] = 1;
+        with_fixed_checksum(&mut output);
+        assert!(matches!(
+            Reader::open(&output),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn large_archive() {
+        // Well past what 16-bit offsets and lengths could address.
+        // The contents are pseudo-random so that the archive doesn't compress below that.
+        let mut state = 0x2545_F491_u32;
+        let mut next_char = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            char::from(b'a' + (state % 26) as u8)
+        };
+
+        let long_record: String = (0..40_000).map(|_| next_char()).collect();
+        let records: Vec<String> = (0..3000)
+            .map(|i| (0..i % 40).map(|_| next_char()).collect())
+            .chain(std::iter::once(long_record))
+            .collect();
+
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        for record in &records {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(output.len() > 0x1_0000);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(records.len(), reader.num_records());
+
+        let mut buf = vec![0_u8; 40_000];
+        for (i, record) in records.iter().enumerate().rev().step_by(97) {
+            assert_eq!(
+                reader.record(i, &mut buf).unwrap(),
+                Some(record.as_bytes()),
+                "{i}"
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_entries_for_huffman() {
+        // More ids are used than Huffman codes can cover, so that codec has to be skipped.
+        let records: Vec<_> = (0..40_000_u32)
+            .map(|i| i.to_be_bytes()[1..].to_vec())
+            .collect();
+
+        let mut writer = Writer::with_options(CompressOptions::new().dict_size(100_000));
+        for record in records.iter().chain(&records) {
+            writer.add_record(record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_ne!(output[CODEC_ADDR], HUFFMAN);
+
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 3];
+        for record in records.iter().chain(&records) {
+            assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&record[..]));
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn integer_records() {
+        let unsigned = [0, 1, 127, 128, 1969, 100_756, u64::MAX];
+        let signed = [0, -1, 1, -64, 64, i64::MIN, i64::MAX];
+
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        for val in &unsigned {
+            writer.add_u64(*val);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(reader.record_kind(), RecordKind::Unsigned);
+        for val in &unsigned {
+            assert_eq!(reader.next_u64().unwrap(), Some(*val));
+        }
+        assert_eq!(reader.next_u64().unwrap(), None);
+
+        reader.seek(5).unwrap();
+        assert_eq!(reader.next_u64().unwrap(), Some(100_756));
+        assert!(matches!(reader.next_i64(), Err(ErrorKind::WrongRecordKind)));
+
+        let mut writer = Writer::new();
+        for val in &signed {
+            writer.add_i64(*val);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(reader.record_kind(), RecordKind::Signed);
+        for val in &signed {
+            assert_eq!(reader.next_i64().unwrap(), Some(*val));
+        }
+        assert_eq!(reader.next_i64().unwrap(), None);
+
+        // Text archives aren't integers, whichever codec they were stored with.
+        let mut writer = Writer::new();
+        writer.add_record("1969");
+        writer.add_record("R75");
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(reader.record_kind(), RecordKind::Bytes);
+        assert!(matches!(reader.next_u64(), Err(ErrorKind::WrongRecordKind)));
+
+        // Values close together are stored as numbers, and read back without a LEB128 round trip.
+        let unsigned: Vec<u64> = (0..200).map(|i| 100_000 + i * 37 % 1000).collect();
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        for val in &unsigned {
+            writer.add_u64(*val);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(NUMERIC, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open(&output).unwrap();
+        assert_eq!(
+            reader.u64_records().collect::<Result<Vec<_>, _>>().unwrap(),
+            unsigned
+        );
+        reader.seek(150).unwrap();
+        assert_eq!(reader.next_u64().unwrap(), Some(unsigned[150]));
+        assert!(matches!(reader.next_i64(), Err(ErrorKind::WrongRecordKind)));
+
+        let mut buf = [0_u8; LEB128_MAX_LEN];
+        assert_eq!(
+            reader.next_record(&mut buf).unwrap(),
+            Some(Leb128::encode(unsigned[151]).as_ref())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't mix record kinds in one archive")]
+    fn mixed_record_kinds() {
+        let mut writer = Writer::new();
+        writer.add_u64(12);
+        writer.add_record("12");
+    }
+
+    fn check_sections(path: &str, record_index: bool) {
+        let input_text = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+        let (first, second) = lines.split_at(lines.len() / 3);
+
+        let mut writer = Writer::new();
+        if record_index {
+            writer.emit_record_index();
+        }
+        writer.add_record("before");
+        writer.begin_section("first");
+        for line in first {
+            writer.add_record(*line);
+        }
+        writer.begin_section("empty");
+        writer.begin_section("second");
+        for line in second {
+            writer.add_record(*line);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let reader = Reader::open(&output).unwrap();
+        assert_eq!(lines.len() + 1, reader.num_records());
+        assert_eq!(4, reader.num_sections());
+        assert_eq!(Some(&b""[..]), reader.section_name(0));
+        assert_eq!(Some(&b"second"[..]), reader.section_name(3));
+        assert_eq!(None, reader.section_name(4));
+        assert!(reader.section(4).unwrap().is_none());
+        assert!(reader.section_named("third").unwrap().is_none());
+
+        for n in 0..reader.num_sections() {
+            let num_records = reader.section(n).unwrap().unwrap().num_records();
+            let limits = ArchiveLimits::read_section(&output, n).unwrap();
+            assert_eq!(num_records, limits.num_records);
+            assert_eq!(
+                reader.limits().unwrap().max_record_len,
+                limits.max_record_len
+            );
+        }
+        assert_eq!(ArchiveLimits::read_section(&output, 4), None);
+
+        let mut buf = [0_u8; 512];
+        let mut section = reader.section(0).unwrap().unwrap();
+        assert_eq!(1, section.num_records());
+        assert_eq!(section.next_record(&mut buf).unwrap(), Some(&b"before"[..]));
+        assert_eq!(section.next_record(&mut buf).unwrap(), None);
+
+        let mut section = reader.section_named("empty").unwrap().unwrap();
+        assert_eq!(0, section.num_records());
+        assert_eq!(section.next_record(&mut buf).unwrap(), None);
+
+        for (name, records) in &[("first", first), ("second", second)] {
+            let mut section = reader.section_named(*name).unwrap().unwrap();
+            assert_eq!(records.len(), section.num_records());
+
+            for line in *records {
+                let record = section.next_record(&mut buf).unwrap();
+                assert_eq!(record, Some(line.as_bytes()), "{name}: {line}");
+            }
+            assert_eq!(section.next_record(&mut buf).unwrap(), None);
+            assert_eq!(section.stream_record(|_| ()).unwrap(), None);
+
+            // Seeking is relative to the start of the section.
+            let last = records.len() - 1;
+            let record = section.record(last, &mut buf).unwrap();
+            assert_eq!(record, Some(records[last].as_bytes()));
+            assert_eq!(section.record(last + 1, &mut buf).unwrap(), None);
+
+            section.seek(1).unwrap();
+            let record = section.next_record(&mut buf).unwrap();
+            assert_eq!(record, Some(records[1].as_bytes()));
+        }
+    }
+
+    #[test]
+    fn sections() {
+        for &record_index in &[false, true] {
+            check_sections("test_data/aoc_2007.txt", record_index);
+            check_sections("test_data/aoc_2002.txt", record_index);
+        }
+
+        // An archive without sections has none to return.
+        let mut writer = Writer::new();
+        writer.add_record("Hello");
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let reader = Reader::open(&output).unwrap();
+        assert_eq!(0, reader.num_sections());
+        assert!(reader.section(0).unwrap().is_none());
+        assert_eq!(ArchiveLimits::read_section(&output, 0), None);
+    }
+
+    #[test]
+    fn rejects_bad_sections() {
+        let mut writer = Writer::new();
+        writer.begin_section("a");
+        writer.add_record("Hello");
+        writer.begin_section("b");
+        writer.add_record("World!");
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(Reader::open(&output).is_ok());
+
+        // Claim there's an extra record in section "b".
+        let sections_start = blocks_start(&output);
+        output[sections_start + 6] += 1;
+        with_fixed_checksum(&mut output);
+        assert!(matches!(
+            Reader::open(&output),
+            Err(ErrorKind::SectionOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn shared_dictionary() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+        let (corpus, records) = lines.split_at(lines.len() / 2);
+
+        let dict =
+            SharedDictionary::train(CompressOptions::new(), corpus.iter().map(|r| r.as_bytes()));
+        let stored = dict.store();
+
+        // Small enough that a dictionary of its own would outweigh the records.
+        let mut writer = Writer::new();
+        writer.use_shared_dictionary(&dict);
+        writer.emit_record_index();
+        for record in &records[..10] {
+            writer.add_record(*record);
+        }
+
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(SHARED_DICT, output[CODEC_ADDR]);
+
+        let mut reader = Reader::open_with_dict(&output, &stored).unwrap();
+        let mut buf = [0_u8; 50];
+        for (i, record) in records[..10].iter().enumerate().rev() {
+            assert_eq!(reader.record(i, &mut buf).unwrap(), Some(record.as_bytes()));
+        }
+
+        assert!(matches!(
+            Reader::open(&output),
+            Err(ErrorKind::DictionaryMismatch)
+        ));
+
+        let other =
+            SharedDictionary::train(CompressOptions::new(), records.iter().map(|r| r.as_bytes()));
+        assert!(matches!(
+            Reader::open_with_dict(&output, &other.store()),
+            Err(ErrorKind::DictionaryMismatch)
+        ));
+
+        let mut corrupt = stored.clone();
+        corrupt[5] ^= 1;
+        assert!(matches!(
+            Reader::open_with_dict(&output, &corrupt),
+            Err(ErrorKind::ChecksumMismatch)
+        ));
+
+        // Archives with their own dictionary don't need the shared one.
+        let mut writer = Writer::new();
+        for record in records {
+            writer.add_record(*record);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_ne!(SHARED_DICT, output[CODEC_ADDR]);
+        assert!(Reader::open_with_dict(&output, &stored).is_ok());
+    }
+
+    fn read_all(reader: &mut Reader) -> Vec<Vec<u8>> {
+        let mut buf = [0_u8; 512];
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record(&mut buf).unwrap() {
+            records.push(record.to_vec());
+        }
+        records
+    }
+
+    #[test]
+    fn append_raw() {
+        let records = incompressible_records();
+        let mut writer = Writer::new();
+        writer.emit_record_index();
+        writer.begin_section("first");
+        for record in &records[..300] {
+            writer.add_record(record);
+        }
+        writer.begin_section("second");
+        for record in &records[300..400] {
+            writer.add_record(record);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(RAW, output[CODEC_ADDR]);
+
+        let reader = Reader::open(&output).unwrap();
+        let mut writer = Writer::from_reader(&reader, CompressOptions::new()).unwrap();
+        writer.replace_record(0, "replaced");
+        // Would compress well, but the archive stays raw.
+        for _ in 0..100 {
+            writer.add_record("abcabcabcabcabcabcabcabc");
+        }
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+        assert_eq!(RAW, appended[CODEC_ADDR]);
+
+        let mut expected = records[..400].to_vec();
+        expected[0] = b"replaced".to_vec();
+        expected.extend((0..100).map(|_| b"abcabcabcabcabcabcabcabc".to_vec()));
+
+        let mut reader = Reader::open(&appended).unwrap();
+        assert!(reader.has_record_index());
+        assert_eq!(reader.num_sections(), 2);
+        assert_eq!(reader.section_name(1), Some(&b"second"[..]));
+        let mut second = reader.section(1).unwrap().unwrap();
+        assert_eq!(read_all(&mut second), &expected[300..]);
+        assert_eq!(read_all(&mut reader), expected);
+
+        writer.recompress();
+        let mut recompressed: Vec<u8> = Vec::new();
+        writer.write(&mut recompressed).unwrap();
+        let mut reader = Reader::open(&recompressed).unwrap();
+        assert_eq!(read_all(&mut reader), expected);
+    }
+
+    fn dict_entries(archive: &[u8]) -> Vec<Vec<u8>> {
+        match Reader::open(archive).unwrap().payload {
+            Payload::Compressed(c) => c.dict_entries().map(|e| e.unwrap().to_vec()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn append_reuses_dictionary() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2007.txt").unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+
+        let mut writer = Writer::new();
+        for line in &lines {
+            writer.add_record(*line);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert!(matches!(output[CODEC_ADDR], COMPRESSED | HUFFMAN));
+
+        // Records made of the same bytes tokenize with the existing dictionary.
+        let reader = Reader::open(&output).unwrap();
+        let mut writer = Writer::from_reader(&reader, CompressOptions::new()).unwrap();
+        for line in &lines[..20] {
+            writer.add_record(*line);
+        }
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+
+        // Splitting the records with different ids may leave some entries unused, which Huffman
+        // coded archives drop.
+        assert!(matches!(appended[CODEC_ADDR], COMPRESSED | HUFFMAN));
+        let original = dict_entries(&output);
+        assert!(dict_entries(&appended).iter().all(|e| original.contains(e)));
+
+        let mut expected: Vec<_> = lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+        expected.extend(expected[..20].to_vec());
+        assert_eq!(read_all(&mut Reader::open(&appended).unwrap()), expected);
+
+        // A byte the dictionary doesn't have means building a new one.
+        writer.add_record("~");
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+        expected.push(b"~".to_vec());
+        assert_eq!(read_all(&mut Reader::open(&appended).unwrap()), expected);
+    }
+
+    #[test]
+    fn append_shared_dictionary() {
+        let input_text = std::fs::read_to_string("test_data/aoc_2002.txt").unwrap();
+        let lines: Vec<_> = input_text.lines().collect();
+        let (corpus, records) = lines.split_at(lines.len() / 2);
+        let dict =
+            SharedDictionary::train(CompressOptions::new(), corpus.iter().map(|r| r.as_bytes()));
+        let stored = dict.store();
+
+        let mut writer = Writer::new();
+        writer.use_shared_dictionary(&dict);
+        for record in &records[..10] {
+            writer.add_record(*record);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(SHARED_DICT, output[CODEC_ADDR]);
+
+        let reader = Reader::open_with_dict(&output, &stored).unwrap();
+        let mut writer = Writer::from_reader(&reader, CompressOptions::new()).unwrap();
+        writer.add_record(records[10]);
+        let expected: Vec<_> = records[..11]
+            .iter()
+            .map(|r| r.as_bytes().to_vec())
+            .collect();
+
+        // Without the shared dictionary, the records get a dictionary of their own.
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+        assert_ne!(SHARED_DICT, appended[CODEC_ADDR]);
+        assert_eq!(read_all(&mut Reader::open(&appended).unwrap()), expected);
+
+        writer.use_shared_dictionary(&dict);
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+        assert_eq!(SHARED_DICT, appended[CODEC_ADDR]);
+        let mut reader = Reader::open_with_dict(&appended, &stored).unwrap();
+        assert_eq!(read_all(&mut reader), expected);
+    }
+
+    #[test]
+    fn append_integers() {
+        let mut writer = Writer::new();
+        for val in 1000..1100 {
+            writer.add_u64(val);
+        }
+        let mut output: Vec<u8> = Vec::new();
+        writer.write(&mut output).unwrap();
+        assert_eq!(NUMERIC, output[CODEC_ADDR]);
+
+        let reader = Reader::open(&output).unwrap();
+        let mut writer = Writer::from_reader(&reader, CompressOptions::new()).unwrap();
+        writer.replace_u64(5, 7);
+        writer.add_u64(u64::MAX);
+        let mut appended: Vec<u8> = Vec::new();
+        writer.write(&mut appended).unwrap();
+
+        let mut expected: Vec<_> = (1000..1100).collect();
+        expected[5] = 7;
+        expected.push(u64::MAX);
+
+        let mut reader = Reader::open(&appended).unwrap();
+        assert_eq!(reader.record_kind(), RecordKind::Unsigned);
+        let values: Vec<_> = reader.u64_records().map(Result::unwrap).collect();
+        assert_eq!(values, expected);
     }
 }
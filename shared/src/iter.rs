@@ -0,0 +1,314 @@
+use core::marker::PhantomData;
+
+use crate::{ErrorKind, FromRecord, Reader};
+
+/// Iterator over the remaining records of a `Reader`, created by `Reader::map_records`.
+///
+/// Each record is decoded into the caller's buffer and handed to `f`, as the records can't
+/// outlive the buffer.
+/// Iteration stops after the first error.
+pub struct MapRecords<'r, 'a, 'b, F> {
+    reader: &'r mut Reader<'a>,
+    buf: &'b mut [u8],
+    f: F,
+    failed: bool,
+}
+
+impl<'r, 'a, 'b, F> MapRecords<'r, 'a, 'b, F> {
+    pub(crate) fn new(reader: &'r mut Reader<'a>, buf: &'b mut [u8], f: F) -> Self {
+        Self {
+            reader,
+            buf,
+            f,
+            failed: false,
+        }
+    }
+}
+
+impl<F, T> Iterator for MapRecords<'_, '_, '_, F>
+where
+    F: FnMut(&[u8]) -> T,
+{
+    type Item = Result<T, ErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let f = &mut self.f;
+        match self.reader.next_record(self.buf) {
+            Ok(record) => record.map(|r| Ok(f(r))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Iterator over the remaining records of a `Reader` as UTF-8 strings, created by
+/// `Reader::map_str_records`.
+///
+/// Iteration stops after the first error.
+pub struct MapStrRecords<'r, 'a, 'b, F> {
+    reader: &'r mut Reader<'a>,
+    buf: &'b mut [u8],
+    f: F,
+    failed: bool,
+}
+
+impl<'r, 'a, 'b, F> MapStrRecords<'r, 'a, 'b, F> {
+    pub(crate) fn new(reader: &'r mut Reader<'a>, buf: &'b mut [u8], f: F) -> Self {
+        Self {
+            reader,
+            buf,
+            f,
+            failed: false,
+        }
+    }
+}
+
+impl<F, T> Iterator for MapStrRecords<'_, '_, '_, F>
+where
+    F: FnMut(&str) -> T,
+{
+    type Item = Result<T, ErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let record = self.reader.next_record(self.buf).and_then(|record| {
+            record
+                .map(|r| core::str::from_utf8(r).map_err(|_| ErrorKind::InvalidUtf8))
+                .transpose()
+        });
+
+        let f = &mut self.f;
+        match record {
+            Ok(record) => record.map(|r| Ok(f(r))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Iterator over the remaining records of an archive of integers, created by
+/// `Reader::u64_records` or `Reader::i64_records`.
+///
+/// Iteration stops after the first error.
+pub struct IntegerRecords<'r, 'a, T> {
+    reader: &'r mut Reader<'a>,
+    next: fn(&mut Reader<'a>) -> Result<Option<T>, ErrorKind>,
+    failed: bool,
+}
+
+impl<'r, 'a, T> IntegerRecords<'r, 'a, T> {
+    pub(crate) fn new(
+        reader: &'r mut Reader<'a>,
+        next: fn(&mut Reader<'a>) -> Result<Option<T>, ErrorKind>,
+    ) -> Self {
+        Self {
+            reader,
+            next,
+            failed: false,
+        }
+    }
+}
+
+impl<T> Iterator for IntegerRecords<'_, '_, T> {
+    type Item = Result<T, ErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let val = (self.next)(self.reader).transpose();
+        self.failed = matches!(val, Some(Err(_)));
+        val
+    }
+}
+
+/// Iterator over the remaining records of a `Reader`, each parsed as a `T`, created by
+/// `Reader::parsed_records`.
+///
+/// Iteration stops after the first error.
+pub struct ParsedRecords<'r, 'a, 'b, T> {
+    reader: &'r mut Reader<'a>,
+    buf: &'b mut [u8],
+    failed: bool,
+    parsed: PhantomData<T>,
+}
+
+impl<'r, 'a, 'b, T> ParsedRecords<'r, 'a, 'b, T> {
+    pub(crate) fn new(reader: &'r mut Reader<'a>, buf: &'b mut [u8]) -> Self {
+        Self {
+            reader,
+            buf,
+            failed: false,
+            parsed: PhantomData,
+        }
+    }
+}
+
+impl<T: FromRecord> Iterator for ParsedRecords<'_, '_, '_, T> {
+    type Item = Result<T, ErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let val = self.reader.next_parsed(self.buf).transpose();
+        self.failed = matches!(val, Some(Err(_)));
+        val
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorKind, Reader, Writer};
+
+    fn write_records(records: &[&[u8]]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for record in records {
+            writer.add_record(*record);
+        }
+
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn map_records() {
+        let output = write_records(&[b"12", b"345", b"6", b"78"]);
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 3];
+
+        let lens: Vec<_> = reader
+            .map_records(&mut buf, <[u8]>::len)
+            .take(3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lens, [2, 3, 1]);
+
+        // The reader carries on from where the iterator stopped.
+        let rest: Vec<_> = reader
+            .map_records(&mut buf, <[u8]>::to_vec)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rest, [b"78"]);
+    }
+
+    #[test]
+    fn map_str_records() {
+        let output = write_records(&[b"12", b"345", b"6", b"78"]);
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 3];
+
+        let mut memory = [0_u32; 3];
+        let values = reader.map_str_records(&mut buf, |r| r.parse::<u32>().unwrap());
+        for (dst, val) in memory.iter_mut().zip(values) {
+            *dst = val.unwrap();
+        }
+        assert_eq!(memory, [12, 345, 6]);
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let output = write_records(&[b"12", &[0xFF, 0xFE], b"6"]);
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 3];
+
+        let mut values = reader.map_str_records(&mut buf, str::len);
+        assert!(matches!(values.next(), Some(Ok(2))));
+        assert!(matches!(values.next(), Some(Err(ErrorKind::InvalidUtf8))));
+        assert!(values.next().is_none());
+
+        let mut small_buf = [0_u8; 1];
+        reader.seek(0).unwrap();
+        let mut values = reader.map_records(&mut small_buf, <[u8]>::len);
+        assert!(matches!(
+            values.next(),
+            Some(Err(ErrorKind::BufferTooSmall {
+                record: 0,
+                needed: 2
+            }))
+        ));
+        assert!(values.next().is_none());
+    }
+
+    #[test]
+    fn integer_records() {
+        let mut writer = Writer::new();
+        for val in &[12, 345, 6, 78] {
+            writer.add_u64(*val);
+        }
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        let sum: u64 = reader.u64_records().map(Result::unwrap).sum();
+        assert_eq!(sum, 12 + 345 + 6 + 78);
+
+        reader.seek(0).unwrap();
+        let mut values = reader.i64_records();
+        assert!(matches!(
+            values.next(),
+            Some(Err(ErrorKind::WrongRecordKind))
+        ));
+        assert!(values.next().is_none());
+    }
+
+    #[test]
+    fn parsed_records() {
+        let output = write_records(&[b"U12", b"L-3", b"R7"]);
+        let mut reader = Reader::open(&output).unwrap();
+        let mut buf = [0_u8; 3];
+
+        let moves: Vec<(u8, i16)> = reader
+            .parsed_records(&mut buf)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(moves, [(b'U', 12), (b'L', -3), (b'R', 7)]);
+
+        reader.seek(0).unwrap();
+        let mut values = reader.parsed_records::<(u8, u8)>(&mut buf);
+        assert!(matches!(values.next(), Some(Ok((b'U', 12)))));
+        assert!(matches!(
+            values.next(),
+            Some(Err(ErrorKind::InvalidField {
+                record: 1,
+                column: 1
+            }))
+        ));
+        assert!(values.next().is_none());
+
+        // Integer archives skip the text.
+        let mut writer = Writer::new();
+        for val in &[-5, 300, 7] {
+            writer.add_i64(*val);
+        }
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+
+        let mut reader = Reader::open(&output).unwrap();
+        let values: Vec<i16> = reader
+            .parsed_records(&mut [])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values, [-5, 300, 7]);
+
+        reader.seek(0).unwrap();
+        assert!(matches!(
+            reader.next_parsed::<u8>(&mut []),
+            Err(ErrorKind::InvalidInteger)
+        ));
+    }
+}
@@ -6,65 +6,285 @@ extern crate std;
 
 #[cfg(any(feature = "std", test))]
 mod compress;
+mod crc;
 mod decompress;
+mod info;
 mod input;
+mod iter;
+mod limits;
+mod lzss;
+mod numeric;
+mod parse;
+#[cfg(any(feature = "std", test))]
+mod preload;
+#[cfg(any(feature = "std", test))]
+mod spec;
 
+#[cfg(any(feature = "std", test))]
+pub use compress::{CompressOptions, CompressionLevel, SharedDictionary, SortHeuristic};
+pub use info::*;
 pub use input::*;
+pub use iter::*;
+pub use limits::ArchiveLimits;
+pub use parse::*;
+#[cfg(any(feature = "std", test))]
+pub use preload::*;
+#[cfg(any(feature = "std", test))]
+pub use spec::*;
+
+// Archive header, common to all codecs. Since version 5, it's followed by `ArchiveLimits`.
+const MAGIC: [u8; 4] = *b"AOCA";
+const MAGIC_ADDR: core::ops::Range<usize> = 0..4;
+const VERSION_ADDR: usize = 4;
+const CODEC_ADDR: usize = 5;
+const FLAGS_ADDR: usize = 6;
+const HEADER_LEN: usize = 7;
+
+// Optional blocks following the header, stored in the order of their flag bits.
+const FLAG_RECORD_INDEX: u8 = 0b0000_0001;
+const FLAG_RECORD_KIND: u8 = 0b0000_0010;
+const FLAG_SECTIONS: u8 = 0b0000_0100;
+const KNOWN_FLAGS: u8 = FLAG_RECORD_INDEX | FLAG_RECORD_KIND | FLAG_SECTIONS;
+
+// CRC-32 of everything before it, stored at the end of the archive.
+const CHECKSUM_LEN: usize = 4;
 
+// Start of a dictionary stored apart from the archives using it. The lookup table and dictionary
+// follow, then a CRC-32 which archives use as the dictionary's id.
+const DICT_MAGIC: [u8; 4] = *b"AOCD";
+
+// Bumped whenever the layout of the archive changes.
+const FORMAT_VERSION: u8 = 5;
+// Oldest version we can still read.
+const MIN_FORMAT_VERSION: u8 = 3;
+// First versions with LEB128 lengths, and with `ArchiveLimits` after the header.
+const WIDE_FORMAT_VERSION: u8 = 4;
+const LIMITS_FORMAT_VERSION: u8 = 5;
+
+// Version 3 stored all offsets as 16-bit values, at fixed positions in the compressed header.
 const ADDR_SIZE: usize = 2;
 const DICT_START_ADDR: core::ops::Range<usize> = 0..2;
 const RECORD_START_ADDR: core::ops::Range<usize> = 2..4;
 const NUM_RECORD_ADDR: core::ops::Range<usize> = 4..6;
 const LOOKUP_START: usize = 6;
 
-const RUN_LEN_MAX_BYTE: u16 = 0x7F;
-const MULTI_BYTE_START: u8 = 0x80;
+// Longest Huffman code the compressor will produce for a dictionary id.
+const HUFFMAN_MAX_CODE_LEN: usize = 15;
+
+const LEB128_CONTINUE: u8 = 0x80;
+const LEB128_MAX_LEN: usize = 10;
 
 #[derive(Debug)]
 pub enum ErrorKind {
-    InvalidCompressedFlag,
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    UnknownCodec(u8),
+    UnsupportedFlags(u8),
+    ChecksumMismatch,
+    HeaderOutOfRange,
+    LookupOutOfRange,
+    DictionaryOutOfRange,
+    RecordsOutOfRange,
+    IndexOutOfRange,
+    SectionOutOfRange,
+    DictionaryMismatch,
+    InvalidUtf8,
+    InvalidInteger,
+    WrongRecordKind,
     LengthDecode,
-    RecordReadError,
+    /// The buffer passed in is too small for record `record`, which is `needed` bytes long.
+    BufferTooSmall {
+        record: usize,
+        needed: usize,
+    },
+    /// Record `record` runs past the end of the archive. `offset` is where the record starts,
+    /// counted from the start of the records.
+    RecordTruncated {
+        record: usize,
+        offset: usize,
+    },
+    /// Record `record` decodes to something other than its stored length, or has an LZSS match
+    /// which copies from outside of it. `offset` is counted as for `RecordTruncated`.
+    InvalidRecord {
+        record: usize,
+        offset: usize,
+    },
+    /// Record `record` couldn't be parsed by `FromRecord`. `column` is where the field which
+    /// failed starts, counted in bytes from the start of the record.
+    InvalidField {
+        record: usize,
+        column: usize,
+    },
     #[cfg(any(feature = "std", test))]
     Io(std::io::Error),
 }
 
+impl ErrorKind {
+    // Codecs don't know which record they're reading, only where it is, so they leave the record
+    // number for the reader to fill in.
+    fn in_record(self, n: usize) -> Self {
+        match self {
+            Self::BufferTooSmall { needed, .. } => Self::BufferTooSmall { record: n, needed },
+            Self::RecordTruncated { offset, .. } => Self::RecordTruncated { record: n, offset },
+            Self::InvalidRecord { offset, .. } => Self::InvalidRecord { record: n, offset },
+            Self::InvalidField { column, .. } => Self::InvalidField { record: n, column },
+            e => e,
+        }
+    }
+}
+
+impl core::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "missing archive or dictionary magic"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {version}")
+            }
+            Self::UnknownCodec(codec) => write!(f, "unknown codec {codec}"),
+            Self::UnsupportedFlags(flags) => write!(f, "unsupported flags {flags:#010b}"),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Self::HeaderOutOfRange => write!(f, "header is truncated or invalid"),
+            Self::LookupOutOfRange => write!(f, "dictionary lookup table is out of range"),
+            Self::DictionaryOutOfRange => write!(f, "dictionary is out of range"),
+            Self::RecordsOutOfRange => write!(f, "records are out of range"),
+            Self::IndexOutOfRange => write!(f, "record index is out of range"),
+            Self::SectionOutOfRange => write!(f, "section table is out of range"),
+            Self::DictionaryMismatch => write!(f, "missing or wrong shared dictionary"),
+            Self::InvalidUtf8 => write!(f, "record is not valid UTF-8"),
+            Self::InvalidInteger => write!(f, "record is not a valid integer"),
+            Self::WrongRecordKind => write!(f, "archive holds a different kind of record"),
+            Self::LengthDecode => write!(f, "can't decode the number of records"),
+            Self::BufferTooSmall { record, needed } => write!(
+                f,
+                "buffer is too small for record {record}, which needs {needed} bytes"
+            ),
+            Self::RecordTruncated { record, offset } => write!(
+                f,
+                "record {record} at offset {offset} runs past the end of the archive"
+            ),
+            Self::InvalidRecord { record, offset } => {
+                write!(f, "record {record} at offset {offset} is corrupt")
+            }
+            Self::InvalidField { record, column } => {
+                write!(f, "record {record} has an invalid field at column {column}")
+            }
+            #[cfg(any(feature = "std", test))]
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
 #[cfg(any(feature = "std", test))]
-impl From<std::io::Error> for ErrorKind {
-    fn from(e: std::io::Error) -> Self {
-        Self::Io(e)
+impl std::error::Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-enum RunLengthEncoded {
-    Single([u8; 1]),
-    Double([u8; 2]),
+/// What the records in an archive hold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RecordKind {
+    /// Arbitrary bytes, usually text.
+    #[default]
+    Bytes,
+    /// Unsigned integers, stored as LEB128.
+    Unsigned,
+    /// Signed integers, zigzag encoded then stored as LEB128.
+    Signed,
 }
 
-impl AsRef<[u8]> for RunLengthEncoded {
-    fn as_ref(&self) -> &[u8] {
+impl RecordKind {
+    fn from_tag(tag: u8) -> Option<RecordKind> {
+        match tag {
+            0 => Some(RecordKind::Bytes),
+            1 => Some(RecordKind::Unsigned),
+            2 => Some(RecordKind::Signed),
+            _ => None,
+        }
+    }
+
+    #[cfg(any(feature = "std", test))]
+    fn tag(self) -> u8 {
         match self {
-            RunLengthEncoded::Single(v) => v.as_ref(),
-            RunLengthEncoded::Double(v) => v.as_ref(),
+            RecordKind::Bytes => 0,
+            RecordKind::Unsigned => 1,
+            RecordKind::Signed => 2,
         }
     }
 }
 
-impl RunLengthEncoded {
-    #[must_use]
-    fn encode(val: u16) -> RunLengthEncoded {
-        // We'll be needing the top bit for the multi-byte marker.
-        assert!(val < 0x7FFF);
-
-        if val > RUN_LEN_MAX_BYTE {
-            let [lb, hb] = val.to_le_bytes();
-            RunLengthEncoded::Double([hb | MULTI_BYTE_START, lb])
-        } else {
-            RunLengthEncoded::Single([val.to_le_bytes()[0]])
+// Maps signed integers to unsigned so that values near zero stay small: 0, -1, 1, -2, 2...
+#[allow(clippy::cast_sign_loss)]
+fn zigzag_encode(val: i64) -> u64 {
+    ((val << 1) ^ (val >> 63)) as u64
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn zigzag_decode(val: u64) -> i64 {
+    ((val >> 1) as i64) ^ -((val & 1) as i64)
+}
+
+// Reads a little-endian address `width` bytes wide.
+fn read_addr(data: &[u8], addr: usize, width: usize) -> Option<usize> {
+    let bytes = data.get(addr..addr.checked_add(width)?)?;
+    let mut buf = [0_u8; core::mem::size_of::<usize>()];
+    buf.get_mut(..width)?.copy_from_slice(bytes);
+    Some(usize::from_le_bytes(buf))
+}
+
+// Number of bytes needed to store every address up to `max_addr`.
+#[cfg(any(feature = "std", test))]
+fn addr_width(max_addr: usize) -> usize {
+    let bits = usize::BITS - max_addr.leading_zeros();
+    bits.div_ceil(8).max(1) as usize
+}
+
+#[cfg(any(feature = "std", test))]
+fn write_addr(dst: &mut Vec<u8>, addr: usize, width: usize) {
+    dst.extend_from_slice(&addr.to_le_bytes()[..width]);
+}
+
+// How the lengths and offsets in an archive are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Layout {
+    // Version 3: 16-bit offsets, with lengths and ids stored as `RunLengthEncoded`.
+    Narrow,
+    // LEB128 lengths and ids, with offsets as wide as the archive needs.
+    Wide,
+}
+
+impl Layout {
+    fn for_version(version: u8) -> Option<Layout> {
+        match version {
+            MIN_FORMAT_VERSION => Some(Layout::Narrow),
+            WIDE_FORMAT_VERSION..=FORMAT_VERSION => Some(Layout::Wide),
+            _ => None,
         }
     }
 
+    fn decode(self, bytes: &[u8]) -> Option<(usize, &[u8])> {
+        match self {
+            Layout::Narrow => RunLengthEncoded::decode(bytes).map(|(v, xs)| (v.into(), xs)),
+            Layout::Wide => Leb128::decode_usize(bytes),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl From<std::io::Error> for ErrorKind {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// Lengths and ids as stored by version 3 archives. Values up to 0x7F take a single byte,
+// anything larger takes two bytes with the top bit of the first byte set.
+struct RunLengthEncoded;
+
+impl RunLengthEncoded {
     #[must_use]
     fn decode(bytes: &[u8]) -> Option<(u16, &[u8])> {
         match bytes {
@@ -77,3 +297,134 @@ impl RunLengthEncoded {
         }
     }
 }
+
+// Unsigned LEB128. Each byte stores 7 bits of the value, least significant first, with the
+// top bit set on every byte but the last.
+#[derive(Debug, Copy, Clone)]
+struct Leb128 {
+    bytes: [u8; LEB128_MAX_LEN],
+    len: usize,
+}
+
+impl AsRef<[u8]> for Leb128 {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Leb128 {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    fn encode(mut val: u64) -> Leb128 {
+        let mut bytes = [0; LEB128_MAX_LEN];
+        let mut len = 0;
+
+        loop {
+            let byte = (val & 0x7F) as u8;
+            val >>= 7;
+
+            if val == 0 {
+                bytes[len] = byte;
+                len += 1;
+                break;
+            }
+
+            bytes[len] = byte | LEB128_CONTINUE;
+            len += 1;
+        }
+
+        Leb128 { bytes, len }
+    }
+
+    #[cfg(any(feature = "std", test))]
+    #[must_use]
+    fn encode_usize(val: usize) -> Leb128 {
+        Leb128::encode(val as u64)
+    }
+
+    #[must_use]
+    fn decode(bytes: &[u8]) -> Option<(u64, &[u8])> {
+        let mut val = 0_u64;
+
+        for (i, &byte) in bytes.iter().enumerate().take(LEB128_MAX_LEN) {
+            let shift = i * 7;
+            let group = u64::from(byte & !LEB128_CONTINUE);
+
+            // Make sure we're not silently dropping bits off the top.
+            if shift + 7 > 64 && group >> (64 - shift) != 0 {
+                return None;
+            }
+            val |= group << shift;
+
+            if byte & LEB128_CONTINUE == 0 {
+                return Some((val, &bytes[i + 1..]));
+            }
+        }
+
+        None
+    }
+
+    #[must_use]
+    fn decode_usize(bytes: &[u8]) -> Option<(usize, &[u8])> {
+        let (val, xs) = Leb128::decode(bytes)?;
+        Some((core::convert::TryInto::try_into(val).ok()?, xs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leb128_round_trip() {
+        let values = [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0xFFFF, 1 << 35, u64::MAX];
+        let lengths = [1, 1, 1, 2, 2, 3, 3, 6, 10];
+
+        for (&val, &len) in values.iter().zip(&lengths) {
+            let mut encoded = Leb128::encode(val).as_ref().to_vec();
+            assert_eq!(encoded.len(), len, "{val}");
+
+            encoded.push(0xAA);
+            assert_eq!(Leb128::decode(&encoded), Some((val, &[0xAA_u8][..])));
+        }
+    }
+
+    #[test]
+    fn leb128_invalid() {
+        // Truncated.
+        assert_eq!(Leb128::decode(&[]), None);
+        assert_eq!(Leb128::decode(&[0x80, 0x80]), None);
+
+        // Too many bits.
+        let mut too_long = [0xFF; 10];
+        too_long[9] = 0x02;
+        assert_eq!(Leb128::decode(&too_long), None);
+        assert_eq!(Leb128::decode(&[0x80; 11]), None);
+    }
+
+    #[test]
+    fn zigzag_round_trip() {
+        let values = [0, -1, 1, -2, 2, i64::MAX, i64::MIN];
+        let encoded = [0, 1, 2, 3, 4, u64::MAX - 1, u64::MAX];
+
+        for (&val, &enc) in values.iter().zip(&encoded) {
+            assert_eq!(zigzag_encode(val), enc);
+            assert_eq!(zigzag_decode(enc), val);
+        }
+    }
+
+    #[test]
+    fn addr_widths() {
+        assert_eq!(addr_width(0), 1);
+        assert_eq!(addr_width(0xFF), 1);
+        assert_eq!(addr_width(0x100), 2);
+        assert_eq!(addr_width(0xFFFF), 2);
+        assert_eq!(addr_width(0x1_0000), 3);
+
+        let mut buf = Vec::new();
+        write_addr(&mut buf, 0x1_2345, 3);
+        assert_eq!(buf, [0x45, 0x23, 0x01]);
+        assert_eq!(read_addr(&buf, 0, 3), Some(0x1_2345));
+        assert_eq!(read_addr(&buf, 1, 3), None);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{
+    FLAGS_ADDR, FLAG_RECORD_INDEX, FLAG_RECORD_KIND, FLAG_SECTIONS, FORMAT_VERSION, HEADER_LEN,
+    LEB128_CONTINUE, LIMITS_FORMAT_VERSION, MAGIC, VERSION_ADDR,
+};
+
+/// Sizes of an archive's records, stored in its header so that buffers for reading them can be
+/// sized at compile time, as in `[0; ArchiveLimits::of(INPUT).max_record_len]`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveLimits {
+    pub num_records: usize,
+    /// Longest record, as returned by `Reader::next_record`. Integers are LEB128 encoded.
+    pub max_record_len: usize,
+    /// Largest a record gets once decoded. That's `max_record_len` for records of bytes, or the
+    /// number of bytes needed to hold the largest integer for integer records, so a
+    /// `max_decoded_len` of 2 means every record fits in an `i16` or `u16`.
+    pub max_decoded_len: usize,
+}
+
+impl ArchiveLimits {
+    /// Reads the limits from the header of `archive`. Returns `None` if `archive` doesn't start
+    /// with an archive header, or was written by a format version which didn't store them.
+    ///
+    /// Only the header is read, so the archive may still fail to open.
+    #[must_use]
+    pub const fn read(archive: &[u8]) -> Option<ArchiveLimits> {
+        if archive.len() < HEADER_LEN {
+            return None;
+        }
+
+        let mut i = 0;
+        while i < MAGIC.len() {
+            if archive[i] != MAGIC[i] {
+                return None;
+            }
+            i += 1;
+        }
+
+        let version = archive[VERSION_ADDR];
+        if version < LIMITS_FORMAT_VERSION || version > FORMAT_VERSION {
+            return None;
+        }
+
+        match Self::decode(archive, HEADER_LEN) {
+            Some((limits, _)) => Some(limits),
+            None => None,
+        }
+    }
+
+    /// Same as `read`, for use in constants.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `read` would return `None`, which fails the build when evaluated in a constant.
+    #[must_use]
+    pub const fn of(archive: &[u8]) -> ArchiveLimits {
+        match Self::read(archive) {
+            Some(limits) => limits,
+            None => panic!("Not an archive, or written without limits in its header"),
+        }
+    }
+
+    /// Reads the limits of section `n` of `archive`, for buffers which only ever hold one
+    /// section. The record count is the section's own, from the section table, while the lengths
+    /// are the whole archive's. Returns `None` if `read` would, or if there's no section `n`.
+    #[must_use]
+    pub const fn read_section(archive: &[u8], n: usize) -> Option<ArchiveLimits> {
+        let Some(limits) = Self::read(archive) else {
+            return None;
+        };
+        let Some((_, mut pos)) = Self::decode(archive, HEADER_LEN) else {
+            return None;
+        };
+
+        // Skip the blocks before the section table, as `Reader::open` does.
+        let flags = archive[FLAGS_ADDR];
+        if flags & FLAG_SECTIONS == 0 {
+            return None;
+        }
+        if flags & FLAG_RECORD_INDEX != 0 {
+            let Some((num_entries, width_pos)) = decode_usize(archive, pos) else {
+                return None;
+            };
+            if width_pos >= archive.len() {
+                return None;
+            }
+            #[allow(clippy::cast_lossless)]
+            let width = archive[width_pos] as usize;
+            let Some(index_len) = num_entries.checked_mul(width) else {
+                return None;
+            };
+            let Some(end) = (width_pos + 1).checked_add(index_len) else {
+                return None;
+            };
+            pos = end;
+        }
+        if flags & FLAG_RECORD_KIND != 0 {
+            pos += 1;
+        }
+
+        let Some((num_sections, mut pos)) = decode_usize(archive, pos) else {
+            return None;
+        };
+        if n >= num_sections {
+            return None;
+        }
+
+        let mut i = 0;
+        loop {
+            let Some((name_len, name_start)) = decode_usize(archive, pos) else {
+                return None;
+            };
+            let Some(name_end) = name_start.checked_add(name_len) else {
+                return None;
+            };
+            let Some((num_records, end)) = decode_usize(archive, name_end) else {
+                return None;
+            };
+
+            if i == n {
+                return Some(ArchiveLimits {
+                    num_records,
+                    ..limits
+                });
+            }
+            pos = end;
+            i += 1;
+        }
+    }
+
+    /// Same as `read_section`, for use in constants.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `read_section` would return `None`, which fails the build when evaluated in a
+    /// constant.
+    #[must_use]
+    pub const fn of_section(archive: &[u8], n: usize) -> ArchiveLimits {
+        match Self::read_section(archive, n) {
+            Some(limits) => limits,
+            None => panic!("Not an archive, or doesn't have that section"),
+        }
+    }
+
+    // Decodes the limits stored at `pos`, also returning where they end.
+    pub(crate) const fn decode(data: &[u8], pos: usize) -> Option<(ArchiveLimits, usize)> {
+        let Some((num_records, pos)) = decode_usize(data, pos) else {
+            return None;
+        };
+        let Some((max_record_len, pos)) = decode_usize(data, pos) else {
+            return None;
+        };
+        let Some((max_decoded_len, pos)) = decode_usize(data, pos) else {
+            return None;
+        };
+
+        let limits = ArchiveLimits {
+            num_records,
+            max_record_len,
+            max_decoded_len,
+        };
+        Some((limits, pos))
+    }
+}
+
+// `Leb128::decode_usize` for const fns, decoding the value at `pos` and returning where it ends.
+const fn decode_usize(bytes: &[u8], mut pos: usize) -> Option<(usize, usize)> {
+    let mut val = 0_usize;
+    let mut shift = 0;
+
+    while pos < bytes.len() {
+        let byte = bytes[pos];
+        pos += 1;
+
+        #[allow(clippy::cast_lossless)]
+        let group = (byte & !LEB128_CONTINUE) as usize;
+        // Make sure we're not silently dropping bits off the top.
+        if shift >= usize::BITS || (shift > 0 && group >> (usize::BITS - shift) != 0) {
+            return None;
+        }
+        val |= group << shift;
+
+        if byte & LEB128_CONTINUE == 0 {
+            return Some((val, pos));
+        }
+        shift += 7;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Leb128;
+
+    #[test]
+    fn decode_usize_matches_leb128() {
+        for &val in &[0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX] {
+            let mut encoded = vec![0xAA];
+            encoded.extend_from_slice(Leb128::encode_usize(val).as_ref());
+            assert_eq!(decode_usize(&encoded, 1), Some((val, encoded.len())));
+        }
+
+        assert_eq!(decode_usize(&[0x80, 0x80], 0), None);
+        assert_eq!(decode_usize(&[0x80; 11], 0), None);
+    }
+}
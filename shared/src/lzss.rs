@@ -0,0 +1,611 @@
+#[cfg(any(feature = "std", test))]
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use crate::{ErrorKind, Leb128};
+
+// Matches shorter than this are never worth encoding.
+#[cfg(any(feature = "std", test))]
+const MIN_MATCH_LEN: usize = 3;
+
+// How many earlier occurrences of a sequence the encoder checks when looking for a match.
+#[cfg(any(feature = "std", test))]
+const MAX_CANDIDATES: usize = 64;
+
+// Size of the buffer used by `stream_record` to hand out matched bytes.
+const STREAM_CHUNK_LEN: usize = 16;
+
+// Each record is compressed on its own, so that the decoder's window is the output buffer.
+//
+// The payload is the number of records, followed by each record. A record is its decoded
+// length, the length of its body, then the body. The body alternates between a literal run (its
+// length, then the bytes) and a match (its length, then how far back in the record to copy
+// from), starting with a literal run, until the decoded length is reached.
+#[derive(Debug, Clone)]
+pub struct Lzss<'a> {
+    records: &'a [u8],
+    num_records: usize,
+
+    // Stores the index into the `records` field above for the start of the next record.
+    current_record: usize,
+}
+
+impl<'a> Lzss<'a> {
+    /// # Errors
+    ///
+    /// Returns an error if the number of records can't be read.
+    pub(crate) fn open(data: &'a [u8]) -> Result<Self, ErrorKind> {
+        let (num_records, records) =
+            Leb128::decode_usize(data).ok_or(ErrorKind::HeaderOutOfRange)?;
+
+        Ok(Self {
+            records,
+            num_records,
+            current_record: 0,
+        })
+    }
+
+    #[must_use]
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.current_record
+    }
+
+    pub(crate) fn set_position(&mut self, position: usize) {
+        self.current_record = position;
+    }
+
+    pub(crate) fn end_position(&self) -> usize {
+        self.records.len()
+    }
+
+    // Finds the next record, without moving past it.
+    fn peek_record(&self) -> Result<Option<RecordSpan<'a>>, ErrorKind> {
+        let remaining_bytes = self
+            .records
+            .get(self.current_record..)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
+        if remaining_bytes.is_empty() {
+            return Ok(None);
+        }
+
+        let truncated = || ErrorKind::RecordTruncated {
+            record: 0,
+            offset: self.current_record,
+        };
+        let (len, rest) = Leb128::decode_usize(remaining_bytes).ok_or_else(truncated)?;
+        let (body_len, rest) = Leb128::decode_usize(rest).ok_or_else(truncated)?;
+        let body = rest.get(..body_len).ok_or_else(truncated)?;
+
+        Ok(Some(RecordSpan {
+            len,
+            body,
+            start: self.current_record,
+            next_record: self.records.len() - (rest.len() - body_len),
+        }))
+    }
+
+    /// Length of the next record once decoded, which LZSS records store, without moving past it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated.
+    pub(crate) fn record_len(&self) -> Result<Option<usize>, ErrorKind> {
+        Ok(self.peek_record()?.map(|record| record.len))
+    }
+
+    /// Moves past the next record without decoding it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated.
+    pub(crate) fn skip_record(&mut self) -> Result<Option<()>, ErrorKind> {
+        Ok(self
+            .peek_record()?
+            .map(|record| self.current_record = record.next_record))
+    }
+
+    /// Same as `skip_record`, but returns the number of literal runs and matches the record is
+    /// made of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record is truncated or a match copies from outside of it.
+    pub(crate) fn count_tokens(&mut self) -> Result<Option<usize>, ErrorKind> {
+        let Some(record) = self.peek_record()? else {
+            return Ok(None);
+        };
+
+        let mut tokens = Tokens::new(&record);
+        let mut count = 0;
+        while tokens.next_token()?.is_some() {
+            count += 1;
+        }
+
+        self.current_record = record.next_record;
+        Ok(Some(count))
+    }
+
+    /// Reads the next record from the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * Record length exceeds output buffer length.
+    /// * Record length exceeds remaining file length.
+    /// * A match copies from outside of the record.
+    pub fn next_record<'b>(&mut self, dst: &'b mut [u8]) -> Result<Option<&'b [u8]>, ErrorKind> {
+        let Some(record) = self.peek_record()? else {
+            return Ok(None);
+        };
+        let dst = dst.get_mut(..record.len).ok_or(ErrorKind::BufferTooSmall {
+            record: 0,
+            needed: record.len,
+        })?;
+
+        let mut tokens = Tokens::new(&record);
+        let mut end = 0;
+        while let Some(token) = tokens.next_token()? {
+            match token {
+                Token::Literals(bytes) => {
+                    dst[end..end + bytes.len()].copy_from_slice(bytes);
+                    end += bytes.len();
+                }
+                Token::Match { len, distance } => {
+                    // Copied a byte at a time, as a match may overlap the bytes it produces.
+                    for i in end..end + len {
+                        dst[i] = dst[i - distance];
+                    }
+                    end += len;
+                }
+            }
+        }
+
+        self.current_record = record.next_record;
+        Ok(Some(dst))
+    }
+
+    /// Reads the next record from the file, passing it to `f` in one or more pieces.
+    ///
+    /// The last `window.len()` bytes handed out are kept in `window`, so that matches reaching
+    /// no further back than that are copied from it. Matches reaching further are followed back
+    /// to a literal a byte at a time, which is much slower, so `window` may be empty but
+    /// shouldn't be for long records.
+    ///
+    /// Returns the total length of the record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error:
+    ///
+    /// * On failure to read record length.
+    /// * Record length exceeds remaining file length.
+    /// * A match copies from outside of the record.
+    pub fn stream_record(
+        &mut self,
+        window: &mut [u8],
+        mut f: impl FnMut(&[u8]),
+    ) -> Result<Option<usize>, ErrorKind> {
+        let Some(record) = self.peek_record()? else {
+            return Ok(None);
+        };
+
+        // Byte `i` of the record is kept at `i % window.len()`.
+        let window_len = window.len();
+        let mut tokens = Tokens::new(&record);
+        let mut end = 0;
+        while let Some(token) = tokens.next_token()? {
+            match token {
+                Token::Literals(bytes) => {
+                    f(bytes);
+                    let skipped = bytes.len().saturating_sub(window_len);
+                    for (i, &byte) in (end + skipped..).zip(&bytes[skipped..]) {
+                        window[i % window_len] = byte;
+                    }
+                    end += bytes.len();
+                }
+                Token::Match { len, distance } => {
+                    let mut chunk = [0_u8; STREAM_CHUNK_LEN];
+                    let mut chunk_len = 0;
+                    for i in end..end + len {
+                        let byte = if distance <= window_len {
+                            window[(i - distance) % window_len]
+                        } else {
+                            byte_at(&record, i - distance)?
+                        };
+                        if window_len > 0 {
+                            window[i % window_len] = byte;
+                        }
+                        chunk[chunk_len] = byte;
+                        chunk_len += 1;
+
+                        if chunk_len == chunk.len() {
+                            f(&chunk);
+                            chunk_len = 0;
+                        }
+                    }
+                    if chunk_len > 0 {
+                        f(&chunk[..chunk_len]);
+                    }
+                    end += len;
+                }
+            }
+        }
+
+        self.current_record = record.next_record;
+        Ok(Some(record.len))
+    }
+}
+
+// Finds byte `pos` of a record by following matches back until it lands on a literal.
+// The tokens up to `pos` must already have been checked.
+fn byte_at(record: &RecordSpan, mut pos: usize) -> Result<u8, ErrorKind> {
+    'search: loop {
+        let mut tokens = Tokens::new(record);
+        let mut start = 0;
+        while let Some(token) = tokens.next_token()? {
+            match token {
+                Token::Literals(bytes) => {
+                    if pos < start + bytes.len() {
+                        return Ok(bytes[pos - start]);
+                    }
+                    start += bytes.len();
+                }
+                Token::Match { len, distance } => {
+                    if pos < start + len {
+                        pos -= distance;
+                        continue 'search;
+                    }
+                    start += len;
+                }
+            }
+        }
+
+        return Err(ErrorKind::InvalidRecord {
+            record: 0,
+            offset: record.start,
+        });
+    }
+}
+
+struct RecordSpan<'a> {
+    // Length once decoded.
+    len: usize,
+    body: &'a [u8],
+    // Where this record and the one after it start.
+    start: usize,
+    next_record: usize,
+}
+
+enum Token<'a> {
+    Literals(&'a [u8]),
+    Match { len: usize, distance: usize },
+}
+
+// Splits a record body into tokens, checking each one stays inside the record.
+struct Tokens<'a> {
+    body: &'a [u8],
+    decoded_len: usize,
+    produced: usize,
+    next_is_match: bool,
+    // Where the record starts, for errors.
+    start: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(record: &RecordSpan<'a>) -> Self {
+        Self {
+            body: record.body,
+            decoded_len: record.len,
+            produced: 0,
+            next_is_match: false,
+            start: record.start,
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, ErrorKind> {
+        let remaining = self.decoded_len - self.produced;
+        if remaining == 0 {
+            return Ok(None);
+        }
+
+        let truncated = || ErrorKind::RecordTruncated {
+            record: 0,
+            offset: self.start,
+        };
+        let invalid = ErrorKind::InvalidRecord {
+            record: 0,
+            offset: self.start,
+        };
+
+        let (len, rest) = Leb128::decode_usize(self.body).ok_or_else(truncated)?;
+        if len > remaining {
+            return Err(invalid);
+        }
+
+        let token = if self.next_is_match {
+            let (distance, rest) = Leb128::decode_usize(rest).ok_or_else(truncated)?;
+            if distance == 0 || distance > self.produced {
+                return Err(invalid);
+            }
+
+            self.body = rest;
+            Token::Match { len, distance }
+        } else {
+            let bytes = rest.get(..len).ok_or_else(truncated)?;
+            self.body = &rest[len..];
+            Token::Literals(bytes)
+        };
+
+        self.produced += len;
+        self.next_is_match = !self.next_is_match;
+        Ok(Some(token))
+    }
+}
+
+/// Compresses `records` into a payload readable by `Lzss`.
+#[cfg(any(feature = "std", test))]
+pub(crate) fn store_archive<'r>(records: impl ExactSizeIterator<Item = &'r [u8]>) -> Vec<u8> {
+    let mut archive = Vec::new();
+    archive.extend_from_slice(Leb128::encode_usize(records.len()).as_ref());
+
+    let mut body = Vec::new();
+    for record in records {
+        body.clear();
+        encode_record(record, &mut body);
+
+        archive.extend_from_slice(Leb128::encode_usize(record.len()).as_ref());
+        archive.extend_from_slice(Leb128::encode_usize(body.len()).as_ref());
+        archive.extend_from_slice(&body);
+    }
+
+    archive
+}
+
+#[cfg(any(feature = "std", test))]
+fn encode_record(record: &[u8], body: &mut Vec<u8>) {
+    fn push_len(body: &mut Vec<u8>, val: usize) {
+        body.extend_from_slice(Leb128::encode_usize(val).as_ref());
+    }
+
+    // Where each sequence of `MIN_MATCH_LEN` bytes has been seen so far, most recent last.
+    let mut seen: BTreeMap<&[u8], Vec<usize>> = BTreeMap::new();
+    let remember = |seen: &mut BTreeMap<_, Vec<_>>, pos: usize| {
+        if let Some(key) = record.get(pos..pos + MIN_MATCH_LEN) {
+            seen.entry(key).or_default().push(pos);
+        }
+    };
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos < record.len() {
+        // Take the longest match, preferring the closest if there's a tie as it's cheaper to
+        // encode.
+        let best_match = record
+            .get(pos..pos + MIN_MATCH_LEN)
+            .and_then(|key| seen.get(key))
+            .and_then(|starts| {
+                starts
+                    .iter()
+                    .rev()
+                    .take(MAX_CANDIDATES)
+                    .map(|&start| {
+                        let len = record[start..]
+                            .iter()
+                            .zip(&record[pos..])
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                        (len, pos - start)
+                    })
+                    .max_by_key(|&(len, distance)| (len, Reverse(distance)))
+            })
+            .filter(|&(len, distance)| {
+                // Ending the literal run and starting the next takes a byte as well.
+                let cost = Leb128::encode_usize(len).as_ref().len()
+                    + Leb128::encode_usize(distance).as_ref().len()
+                    + 1;
+                len > cost
+            });
+
+        if let Some((len, distance)) = best_match {
+            push_len(body, pos - literal_start);
+            body.extend_from_slice(&record[literal_start..pos]);
+            push_len(body, len);
+            push_len(body, distance);
+
+            for matched in pos..pos + len {
+                remember(&mut seen, matched);
+            }
+            pos += len;
+            literal_start = pos;
+        } else {
+            remember(&mut seen, pos);
+            pos += 1;
+        }
+    }
+
+    if literal_start < record.len() {
+        push_len(body, record.len() - literal_start);
+        body.extend_from_slice(&record[literal_start..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDS: &[&[u8]] = &[
+        b"",
+        b"a",
+        b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        b"TOBEORNOTTOBEORTOBEORNOT",
+        b"Hello World! Hello World! Hello World!",
+        b"abc",
+    ];
+
+    // Bytes without any repeats worth a match.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    fn long_record() -> Vec<u8> {
+        // Distant repeats, which the dictionary codec can't make use of.
+        let block = noise(300);
+        let mut record = block.clone();
+        record.extend_from_slice(b"some text in between");
+        record.extend_from_slice(&block);
+        record
+    }
+
+    fn read_all(data: &[u8]) -> Result<(), ErrorKind> {
+        let mut reader = Lzss::open(data)?;
+        let mut buf = [0_u8; 1024];
+        while reader.next_record(&mut buf)?.is_some() {}
+
+        let mut reader = Lzss::open(data)?;
+        while reader
+            .stream_record(&mut [], |_| ())
+            .is_ok_and(|len| len.is_some())
+        {}
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() {
+        let long_record = long_record();
+        let records: Vec<&[u8]> = RECORDS
+            .iter()
+            .copied()
+            .chain(Some(&long_record[..]))
+            .collect();
+        let output = store_archive(records.iter().copied());
+
+        // The repeat should have been found.
+        assert!(output.len() < long_record.len() - 200);
+
+        let mut reader = Lzss::open(&output).unwrap();
+        assert_eq!(records.len(), reader.num_records());
+
+        let mut buf = [0_u8; 1024];
+        for record in &records {
+            assert_eq!(reader.next_record(&mut buf).unwrap(), Some(*record));
+        }
+        assert_eq!(reader.next_record(&mut buf).unwrap(), None);
+
+        let mut reader = Lzss::open(&output).unwrap();
+        for record in &records {
+            let mut streamed = Vec::new();
+            let len = reader.stream_record(&mut [], |piece| streamed.extend_from_slice(piece));
+            assert_eq!(len.unwrap(), Some(record.len()));
+            assert_eq!(&streamed, record);
+        }
+        assert_eq!(reader.stream_record(&mut [], |_| ()).unwrap(), None);
+    }
+
+    #[test]
+    fn distant_repeats() {
+        // A repeat further back than any window a caller is likely to have.
+        let block = noise(600);
+        let mut record = block.clone();
+        record.extend_from_slice(&[b'-'; 1000]);
+        record.extend_from_slice(&block);
+        let output = store_archive(Some(&record[..]).into_iter());
+
+        // Both repeats should have been found, including the one 1600 bytes back.
+        assert!(output.len() < block.len() + 50);
+
+        let mut buf = [0_u8; 2400];
+        let mut reader = Lzss::open(&output).unwrap();
+        assert_eq!(reader.next_record(&mut buf).unwrap(), Some(&record[..]));
+    }
+
+    #[test]
+    fn stream_with_window() {
+        // Long runs, which would take a long time to follow back a byte at a time.
+        let runs: Vec<u8> = (0..100_000).map(|i| b"ab"[i / 50 % 2]).collect();
+        let long_record = long_record();
+        let output = store_archive([&runs[..], &long_record].iter().copied());
+
+        let mut window = [0_u8; 128];
+        let mut reader = Lzss::open(&output).unwrap();
+        for record in [&runs, &long_record] {
+            let mut streamed = Vec::new();
+            let len = reader.stream_record(&mut window, |piece| streamed.extend_from_slice(piece));
+            assert_eq!(len.unwrap(), Some(record.len()));
+            assert_eq!(&streamed, record);
+        }
+        assert_eq!(reader.stream_record(&mut window, |_| ()).unwrap(), None);
+
+        // Matches reaching further back than the window still decode, as do ones which don't
+        // once the window is full.
+        let mut window = [0_u8; 7];
+        let mut reader = Lzss::open(&output).unwrap();
+        reader.skip_record().unwrap();
+        let mut streamed = Vec::new();
+        let len = reader.stream_record(&mut window, |piece| streamed.extend_from_slice(piece));
+        assert_eq!(len.unwrap(), Some(long_record.len()));
+        assert_eq!(streamed, long_record);
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let output = store_archive(RECORDS[2..3].iter().copied());
+        let mut reader = Lzss::open(&output).unwrap();
+        let mut buf = [0_u8; 10];
+        let needed = RECORDS[2].len();
+        assert!(matches!(
+            reader.next_record(&mut buf),
+            Err(ErrorKind::BufferTooSmall { needed: n, .. }) if n == needed
+        ));
+    }
+
+    #[test]
+    fn corrupt_archive() {
+        // One record of 4 bytes, starting with a match.
+        assert!(matches!(
+            read_all(&[1, 4, 3, 0, 4, 1]),
+            Err(ErrorKind::InvalidRecord { offset: 0, .. })
+        ));
+
+        // A match reaching back past the start of the record.
+        assert!(matches!(
+            read_all(&[1, 4, 4, 1, b'a', 3, 2]),
+            Err(ErrorKind::InvalidRecord { offset: 0, .. })
+        ));
+
+        let long_record = long_record();
+        let records: Vec<&[u8]> = RECORDS
+            .iter()
+            .copied()
+            .chain(Some(&long_record[..]))
+            .collect();
+        let output = store_archive(records.iter().copied());
+        assert!(read_all(&output).is_ok());
+
+        // None of these should panic.
+        for len in 0..output.len() {
+            let _ = read_all(&output[..len]);
+        }
+
+        let output = store_archive(RECORDS.iter().copied());
+        for i in 0..output.len() {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[i] = val;
+                let _ = read_all(&corrupt);
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use crate::{zigzag_decode, zigzag_encode, ErrorKind, Leb128, RecordKind};
+
+// Each value is the base plus its packed value.
+const MODE_FRAME_OF_REFERENCE: u8 = 0;
+// Each value is the previous one, starting from the base, plus its zigzag encoded packed value.
+const MODE_DELTA: u8 = 1;
+
+const MAX_WIDTH: usize = 64;
+
+/// Longest an integer record gets, which is "-9223372036854775808" as text.
+pub(crate) const MAX_RECORD_LEN: usize = 20;
+
+// Stores a list of integers as a base value, plus a fixed number of bits for each value.
+//
+// The payload is the number of records, the mode, the zigzag encoded base, the number of bits
+// per value, then the values packed most significant bit first.
+#[derive(Debug, Clone)]
+pub struct Numeric<'a> {
+    packed: &'a [u8],
+    num_records: usize,
+    mode: u8,
+    base: i64,
+    width: usize,
+    // Bytes following the packed values, which there shouldn't be.
+    trailing_len: usize,
+
+    current_record: usize,
+    // The value of the record before `current_record`, used in delta mode.
+    prev_value: i64,
+}
+
+impl<'a> Numeric<'a> {
+    /// # Errors
+    ///
+    /// Returns an error if the header is truncated or invalid, or if there are fewer packed
+    /// values than records.
+    pub(crate) fn open(data: &'a [u8]) -> Result<Self, ErrorKind> {
+        let (num_records, rest) = Leb128::decode_usize(data).ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (&mode, rest) = rest.split_first().ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (base, rest) = Leb128::decode(rest).ok_or(ErrorKind::HeaderOutOfRange)?;
+        let (&width, values) = rest.split_first().ok_or(ErrorKind::HeaderOutOfRange)?;
+
+        let width = width as usize;
+        if width > MAX_WIDTH || !matches!(mode, MODE_FRAME_OF_REFERENCE | MODE_DELTA) {
+            return Err(ErrorKind::HeaderOutOfRange);
+        }
+
+        let packed_len = num_records
+            .checked_mul(width)
+            .ok_or(ErrorKind::RecordsOutOfRange)?
+            .div_ceil(8);
+        let packed = values
+            .get(..packed_len)
+            .ok_or(ErrorKind::RecordsOutOfRange)?;
+
+        let base = zigzag_decode(base);
+        Ok(Self {
+            packed,
+            num_records,
+            mode,
+            base,
+            width,
+            trailing_len: values.len() - packed_len,
+            current_record: 0,
+            prev_value: base,
+        })
+    }
+
+    #[must_use]
+    pub fn num_records(&self) -> usize {
+        self.num_records
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.current_record
+    }
+
+    pub(crate) fn set_position(&mut self, position: usize) {
+        self.current_record = position.min(self.num_records);
+
+        // Deltas have to be added up from the start.
+        if self.mode == MODE_DELTA {
+            self.prev_value = (0..self.current_record).fold(self.base, |prev, n| {
+                prev.wrapping_add(zigzag_decode(self.packed_value(n)))
+            });
+        }
+    }
+
+    pub(crate) fn end_position(&self) -> usize {
+        self.num_records
+    }
+
+    pub(crate) fn trailing_len(&self) -> usize {
+        self.trailing_len
+    }
+
+    // Length of the packed values.
+    pub(crate) fn packed_len(&self) -> usize {
+        self.packed.len()
+    }
+
+    fn packed_value(&self, n: usize) -> u64 {
+        // `open` checked that every record's bits are there.
+        let start = n * self.width;
+        (start..start + self.width).fold(0, |val, bit| {
+            let byte = self.packed[bit / 8];
+            val << 1 | u64::from((byte >> (7 - bit % 8)) & 1)
+        })
+    }
+
+    /// Reads the next value.
+    ///
+    /// # Errors
+    ///
+    /// Doesn't currently fail, as `open` checks every value is present.
+    #[allow(clippy::cast_possible_wrap, clippy::unnecessary_wraps)]
+    pub fn next_value(&mut self) -> Result<Option<i64>, ErrorKind> {
+        if self.current_record >= self.num_records {
+            return Ok(None);
+        }
+
+        let packed = self.packed_value(self.current_record);
+        let val = if self.mode == MODE_DELTA {
+            self.prev_value.wrapping_add(zigzag_decode(packed))
+        } else {
+            self.base.wrapping_add(packed as i64)
+        };
+
+        self.prev_value = val;
+        self.current_record += 1;
+        Ok(Some(val))
+    }
+}
+
+/// Writes `val` into `buf` the way a record of `kind` stores it, returning the bytes written.
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+pub(crate) fn record_bytes(kind: RecordKind, val: i64, buf: &mut [u8; MAX_RECORD_LEN]) -> &[u8] {
+    let leb128 = match kind {
+        RecordKind::Unsigned => Leb128::encode(val as u64),
+        RecordKind::Signed => Leb128::encode(zigzag_encode(val)),
+        RecordKind::Bytes => {
+            // Decimal text, written backwards from the end of the buffer.
+            let mut magnitude = val.unsigned_abs();
+            let mut start = buf.len();
+            loop {
+                start -= 1;
+                buf[start] = b'0' + (magnitude % 10) as u8;
+                magnitude /= 10;
+                if magnitude == 0 {
+                    break;
+                }
+            }
+            if val < 0 {
+                start -= 1;
+                buf[start] = b'-';
+            }
+
+            return &buf[start..];
+        }
+    };
+
+    let len = leb128.as_ref().len();
+    buf[..len].copy_from_slice(leb128.as_ref());
+    &buf[..len]
+}
+
+/// Packs `values` into a payload readable by `Numeric`, using whichever mode is smaller.
+#[cfg(any(feature = "std", test))]
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+pub(crate) fn store_archive(values: &[i64]) -> Vec<u8> {
+    fn bit_width(max: u64) -> usize {
+        (u64::BITS - max.leading_zeros()) as usize
+    }
+
+    let min = values.iter().copied().min().unwrap_or(0);
+    let offsets: Vec<u64> = values.iter().map(|&v| v.wrapping_sub(min) as u64).collect();
+
+    let first = values.first().copied().unwrap_or(0);
+    let deltas: Vec<u64> = core::iter::once(first)
+        .chain(values.iter().copied())
+        .zip(values)
+        .map(|(prev, &v)| zigzag_encode(v.wrapping_sub(prev)))
+        .collect();
+
+    let offset_width = bit_width(offsets.iter().copied().max().unwrap_or(0));
+    let delta_width = bit_width(deltas.iter().copied().max().unwrap_or(0));
+    let (mode, base, width, packed) = if delta_width < offset_width {
+        (MODE_DELTA, first, delta_width, deltas)
+    } else {
+        (MODE_FRAME_OF_REFERENCE, min, offset_width, offsets)
+    };
+
+    let mut archive = Vec::new();
+    archive.extend_from_slice(Leb128::encode_usize(values.len()).as_ref());
+    archive.push(mode);
+    archive.extend_from_slice(Leb128::encode(zigzag_encode(base)).as_ref());
+    archive.push(width as u8);
+
+    let mut bits = 0_u8;
+    let mut num_bits = 0;
+    for val in packed {
+        for bit in (0..width).rev() {
+            bits = bits << 1 | ((val >> bit) & 1) as u8;
+            num_bits += 1;
+            if num_bits == 8 {
+                archive.push(bits);
+                bits = 0;
+                num_bits = 0;
+            }
+        }
+    }
+    if num_bits > 0 {
+        archive.push(bits << (8 - num_bits));
+    }
+
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &[u8]) -> Result<Vec<i64>, ErrorKind> {
+        let mut reader = Numeric::open(data)?;
+        let mut values = Vec::new();
+        while let Some(val) = reader.next_value()? {
+            values.push(val);
+        }
+        Ok(values)
+    }
+
+    #[test]
+    fn round_trip() {
+        let cases: &[&[i64]] = &[
+            &[],
+            &[0],
+            &[5, 5, 5],
+            // Similar magnitudes, so frame of reference.
+            &[100_756, 91_346, 125_242, 93_210, 110_000],
+            // Steadily rising, so delta.
+            &[1000, 1003, 1005, 1010, 1011, 1015, 1020],
+            &[i64::MIN, i64::MAX, 0, -1, 1],
+        ];
+
+        for &values in cases {
+            let output = store_archive(values);
+            assert_eq!(read_all(&output).unwrap(), values);
+
+            let mut reader = Numeric::open(&output).unwrap();
+            assert_eq!(values.len(), reader.num_records());
+            for n in (0..values.len()).rev() {
+                reader.set_position(n);
+                assert_eq!(reader.next_value().unwrap(), Some(values[n]));
+            }
+        }
+
+        assert_eq!(
+            store_archive(&[100_756, 91_346, 125_242])[1],
+            MODE_FRAME_OF_REFERENCE
+        );
+        assert_eq!(
+            store_archive(&[1000, 1010, 1020, 1030, 1040])[1],
+            MODE_DELTA
+        );
+
+        // Offsets of up to 10 from a base of 10, so 4 bits each.
+        assert_eq!(
+            store_archive(&[10, 15, 20]),
+            [3, 0, 20, 4, 0b0000_0101, 0b1010_0000]
+        );
+    }
+
+    #[test]
+    fn records() {
+        let mut buf = [0; MAX_RECORD_LEN];
+        for &val in &[0, 7, -7, 1969, i64::MIN, i64::MAX] {
+            let text = val.to_string();
+            assert_eq!(
+                record_bytes(RecordKind::Bytes, val, &mut buf),
+                text.as_bytes()
+            );
+            assert_eq!(
+                record_bytes(RecordKind::Signed, val, &mut buf),
+                Leb128::encode(zigzag_encode(val)).as_ref()
+            );
+        }
+        assert_eq!(
+            record_bytes(RecordKind::Unsigned, 300, &mut buf),
+            [0xAC, 0x02]
+        );
+    }
+
+    #[test]
+    fn corrupt_archive() {
+        // Unknown mode.
+        assert!(matches!(
+            Numeric::open(&[0, 2, 0, 0]),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // Too wide.
+        assert!(matches!(
+            Numeric::open(&[0, 0, 0, 65]),
+            Err(ErrorKind::HeaderOutOfRange)
+        ));
+
+        // Not enough packed values.
+        assert!(matches!(
+            Numeric::open(&[3, 0, 0, 8, 1, 2]),
+            Err(ErrorKind::RecordsOutOfRange)
+        ));
+
+        let output = store_archive(&[1000, 1003, 1005, 1010, -8, i64::MAX]);
+        for len in 0..output.len() {
+            let _ = read_all(&output[..len]);
+        }
+        for idx in 0..output.len() {
+            for val in 0..=255 {
+                let mut corrupt = output.clone();
+                corrupt[idx] = val;
+                let _ = read_all(&corrupt);
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use core::convert::TryFrom;
+
+use crate::ErrorKind;
+
+/// Types which can be parsed from a record, read with `Reader::next_parsed`.
+///
+/// Records are parsed as text, so an integer is its decimal digits. Archives of integer records
+/// skip the text and hand the value to `from_integer` instead, as do text records which were
+/// stored as integers, falling back to `from_record` if that fails.
+pub trait FromRecord: Sized {
+    /// # Errors
+    ///
+    /// Returns `ErrorKind::InvalidField` if `record` isn't a valid `Self`.
+    fn from_record(record: &[u8]) -> Result<Self, ErrorKind>;
+
+    /// Converts a record of an archive of `RecordKind::Unsigned` or `RecordKind::Signed` records.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ErrorKind::WrongRecordKind` unless `Self` is an integer, or
+    /// `ErrorKind::InvalidInteger` if `val` doesn't fit in it.
+    fn from_integer(val: i128) -> Result<Self, ErrorKind> {
+        let _ = val;
+        Err(ErrorKind::WrongRecordKind)
+    }
+}
+
+/// Types which can be parsed from the start of a record, leaving the rest of it to be parsed as
+/// something else. A `(P, T)` tuple parses `P` from the start of the record and `T` from the
+/// rest, so `(u8, i16)` reads "U12" as `(b'U', 12)`.
+pub trait FromPrefix: Sized {
+    /// Returns the value, and how many bytes of `record` it took up.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ErrorKind::InvalidField` if `record` doesn't start with a valid `Self`.
+    fn from_prefix(record: &[u8]) -> Result<(Self, usize), ErrorKind>;
+}
+
+/// Fields separated by `DELIM`, parsed into each member of the tuple `T` in turn. The last field
+/// takes the rest of the record, delimiters and all.
+///
+/// `Fields<(u32, u32), b'-'>` reads "123-456" as `Fields((123, 456))`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fields<T, const DELIM: u8>(pub T);
+
+// A field which failed to parse, `column` bytes into the record.
+fn invalid_field(column: usize) -> ErrorKind {
+    ErrorKind::InvalidField { record: 0, column }
+}
+
+// Moves the column of an error from parsing a field which starts `offset` bytes into the record.
+fn field_at(e: ErrorKind, offset: usize) -> ErrorKind {
+    match e {
+        ErrorKind::InvalidField { record, column } => ErrorKind::InvalidField {
+            record,
+            column: column + offset,
+        },
+        e => e,
+    }
+}
+
+macro_rules! impl_from_record_for_integers {
+    ($($t:ty),*) => {
+        $(
+            impl FromRecord for $t {
+                fn from_record(record: &[u8]) -> Result<Self, ErrorKind> {
+                    core::str::from_utf8(record)
+                        .ok()
+                        .and_then(|text| text.parse().ok())
+                        .ok_or_else(|| invalid_field(0))
+                }
+
+                fn from_integer(val: i128) -> Result<Self, ErrorKind> {
+                    Self::try_from(val).map_err(|_| ErrorKind::InvalidInteger)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_record_for_integers!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl FromRecord for char {
+    fn from_record(record: &[u8]) -> Result<Self, ErrorKind> {
+        match char::from_prefix(record)? {
+            (c, len) if len == record.len() => Ok(c),
+            (_, len) => Err(invalid_field(len)),
+        }
+    }
+}
+
+impl FromPrefix for u8 {
+    fn from_prefix(record: &[u8]) -> Result<(Self, usize), ErrorKind> {
+        let &b = record.first().ok_or_else(|| invalid_field(0))?;
+        Ok((b, 1))
+    }
+}
+
+impl FromPrefix for char {
+    fn from_prefix(record: &[u8]) -> Result<(Self, usize), ErrorKind> {
+        // The length of a UTF-8 sequence is given by its first byte.
+        let len = match record.first() {
+            Some(0..=0x7F) => 1,
+            Some(0xC0..=0xDF) => 2,
+            Some(0xE0..=0xEF) => 3,
+            Some(0xF0..=0xF7) => 4,
+            _ => return Err(invalid_field(0)),
+        };
+
+        let c = record
+            .get(..len)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .and_then(|text| text.chars().next())
+            .ok_or_else(|| invalid_field(0))?;
+        Ok((c, len))
+    }
+}
+
+impl<P: FromPrefix, T: FromRecord> FromRecord for (P, T) {
+    fn from_record(record: &[u8]) -> Result<Self, ErrorKind> {
+        let (prefix, len) = P::from_prefix(record)?;
+        let rest = T::from_record(&record[len..]).map_err(|e| field_at(e, len))?;
+        Ok((prefix, rest))
+    }
+}
+
+// Splits a record into fields one at a time.
+struct FieldSplit<'r> {
+    record: &'r [u8],
+    delim: u8,
+    // Where the next field starts.
+    start: usize,
+}
+
+impl<'r> FieldSplit<'r> {
+    fn new(record: &'r [u8], delim: u8) -> Self {
+        Self {
+            record,
+            delim,
+            start: 0,
+        }
+    }
+
+    fn next<T: FromRecord>(&mut self) -> Result<T, ErrorKind> {
+        let rest = &self.record[self.start..];
+        let len = rest
+            .iter()
+            .position(|&b| b == self.delim)
+            .ok_or_else(|| invalid_field(self.record.len()))?;
+
+        let field = T::from_record(&rest[..len]).map_err(|e| field_at(e, self.start))?;
+        self.start += len + 1;
+        Ok(field)
+    }
+
+    fn last<T: FromRecord>(self) -> Result<T, ErrorKind> {
+        T::from_record(&self.record[self.start..]).map_err(|e| field_at(e, self.start))
+    }
+}
+
+macro_rules! impl_from_record_for_fields {
+    ($($field:ident),+; $last:ident) => {
+        impl<$($field: FromRecord,)+ $last: FromRecord, const DELIM: u8> FromRecord
+            for Fields<($($field,)+ $last), DELIM>
+        {
+            fn from_record(record: &[u8]) -> Result<Self, ErrorKind> {
+                let mut fields = FieldSplit::new(record, DELIM);
+                Ok(Fields(($(fields.next::<$field>()?,)+ fields.last::<$last>()?)))
+            }
+        }
+    };
+}
+
+impl_from_record_for_fields!(A; B);
+impl_from_record_for_fields!(A, B; C);
+impl_from_record_for_fields!(A, B, C; D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column<T: FromRecord>(record: &str) -> Option<usize> {
+        match T::from_record(record.as_bytes()) {
+            Err(ErrorKind::InvalidField { column, .. }) => Some(column),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(u32::from_record(b"1969").unwrap(), 1969);
+        assert_eq!(i16::from_record(b"-40").unwrap(), -40);
+        assert_eq!(column::<u8>("256"), Some(0));
+        assert_eq!(column::<u32>("-1"), Some(0));
+        assert_eq!(column::<u32>(""), Some(0));
+
+        assert_eq!(u8::from_integer(255).unwrap(), 255);
+        assert!(matches!(
+            u8::from_integer(-1),
+            Err(ErrorKind::InvalidInteger)
+        ));
+        assert!(matches!(
+            char::from_integer(65),
+            Err(ErrorKind::WrongRecordKind)
+        ));
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(<(u8, i16)>::from_record(b"U12").unwrap(), (b'U', 12));
+        assert_eq!(
+            <(char, u32)>::from_record("é7".as_bytes()).unwrap(),
+            ('é', 7)
+        );
+        assert_eq!(char::from_record(b"x").unwrap(), 'x');
+
+        assert_eq!(column::<(u8, i16)>(""), Some(0));
+        assert_eq!(column::<(u8, i16)>("Ux"), Some(1));
+        assert_eq!(column::<char>("xy"), Some(1));
+        assert_eq!(char::from_record("\u{FF}".as_bytes()).unwrap(), '\u{FF}');
+        assert_eq!(column::<(char, u8)>("\u{FF}x"), Some(2));
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            Fields::<(u32, u32), b'-'>::from_record(b"123456-654321").unwrap(),
+            Fields((123_456, 654_321))
+        );
+        assert_eq!(
+            Fields::<(u8, (u8, i32), char), b','>::from_record(b"1,R-5,z").unwrap(),
+            Fields((1, (b'R', -5), 'z'))
+        );
+
+        // Missing delimiter.
+        assert_eq!(column::<Fields<(u32, u32), b'-'>>("123"), Some(3));
+        // Bad second field.
+        assert_eq!(column::<Fields<(u32, u32), b'-'>>("123-4x"), Some(4));
+        assert_eq!(column::<Fields<(u8, (u8, i32)), b','>>("1,Rx"), Some(3));
+        // Too many fields leaves the rest in the last one.
+        assert_eq!(column::<Fields<(u32, u32), b'-'>>("1-2-3"), Some(2));
+    }
+}
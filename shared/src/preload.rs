@@ -0,0 +1,76 @@
+// Preloads are recorded as text, so that they can be checked in and read by both the host and
+// `include_archive!`. Each entry is a line of its own, with backslashes and anything that isn't
+// printable ASCII escaped.
+
+/// Writes `preload` in the form `parse_preload` reads back.
+#[must_use]
+pub fn format_preload(preload: &[Vec<u8>]) -> String {
+    preload
+        .iter()
+        .map(|entry| escape_preload_entry(entry) + "\n")
+        .collect()
+}
+
+/// Escapes a single entry the way `format_preload` does, which is also handy for printing one.
+#[must_use]
+pub fn escape_preload_entry(entry: &[u8]) -> String {
+    entry
+        .iter()
+        .map(|&b| match b {
+            b'\\' => "\\\\".to_owned(),
+            b' '..=b'~' => char::from(b).to_string(),
+            _ => format!("\\x{b:02x}"),
+        })
+        .collect()
+}
+
+/// Reads a preload written by `format_preload`. Returns `None` if an escape is invalid.
+#[must_use]
+pub fn parse_preload(text: &str) -> Option<Vec<Vec<u8>>> {
+    text.lines().map(unescape).collect()
+}
+
+fn unescape(line: &str) -> Option<Vec<u8>> {
+    let mut entry = Vec::new();
+    let mut bytes = line.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            entry.push(b);
+            continue;
+        }
+
+        match bytes.next()? {
+            b'\\' => entry.push(b'\\'),
+            b'x' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                entry.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let preload = vec![
+            b"1,".to_vec(),
+            b"a\\b".to_vec(),
+            vec![0, b'\n', 0xFF],
+            Vec::new(),
+        ];
+        let text = format_preload(&preload);
+        assert_eq!(text, "1,\na\\\\b\n\\x00\\x0a\\xff\n\n");
+        assert_eq!(parse_preload(&text), Some(preload));
+
+        assert_eq!(parse_preload(""), Some(Vec::new()));
+        assert_eq!(parse_preload("\\q"), None);
+        assert_eq!(parse_preload("\\x4"), None);
+    }
+}
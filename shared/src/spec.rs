@@ -0,0 +1,476 @@
+// Specs say how each input is packed into an archive. They're recorded as text, so that the host
+// and `include_archive!` both pack an input from the same settings. Each line gives the path to an
+// input, relative to the spec file, followed by options written as they would be in Rust:
+//
+//     "inputs/aoc_1903.txt", delimiter = ',', sections = ["wire 1", "wire 2"], dict_size = 0
+//
+// A line of just `dict = "path"` names the shared dictionary which every archive may be compressed
+// against. Blank lines and lines starting with `#` are skipped.
+
+use crate::{CompressOptions, CompressionLevel, RecordKind, Writer};
+
+/// Everything in a spec file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveSpecs {
+    /// The shared dictionary written by the host, relative to the spec file.
+    pub dict: Option<String>,
+    pub archives: Vec<ArchiveSpec>,
+}
+
+/// How to pack one input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveSpec {
+    /// Relative to the spec file.
+    pub input: String,
+    /// What the records are, from `kind = text | u64 | i64`. Integers are trimmed before parsing.
+    pub kind: RecordKind,
+    /// Splits each line into several records. Without it, each line is a record.
+    pub delimiter: Option<char>,
+    /// Puts each line in a section of its own, named in order.
+    pub sections: Vec<String>,
+    /// Entries to start the dictionary with, from `preload = ["R", "U"]`.
+    pub preload: Vec<String>,
+    /// A preload recorded by the host, relative to the spec file, from `preload = "path"`.
+    pub preload_file: Option<String>,
+    pub max_entry_len: Option<usize>,
+    pub dict_size: Option<usize>,
+}
+
+impl ArchiveSpecs {
+    /// Reads a spec file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message naming the line if one doesn't parse, or if an input is given twice.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut specs = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            specs
+                .parse_line(line)
+                .map_err(|msg| format!("Line {}: {msg}", i + 1))?;
+        }
+
+        Ok(specs)
+    }
+
+    /// Finds the spec for `input`, given as it's written in the spec file.
+    #[must_use]
+    pub fn get(&self, input: &str) -> Option<&ArchiveSpec> {
+        self.archives.iter().find(|spec| spec.input == input)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let tokens = tokenize(line)?;
+        let mut args = split_commas(&tokens).into_iter();
+
+        let input = match args.next().as_deref() {
+            Some([lit]) if lit.starts_with('"') => parse_str(lit)?,
+            Some(["dict", "=", lit]) if args.len() == 0 => {
+                if self.dict.is_some() {
+                    return Err("The dictionary is already given".to_owned());
+                }
+                self.dict = Some(parse_str(lit)?);
+                return Ok(());
+            }
+            _ => return Err("Expected the path to an input first".to_owned()),
+        };
+        if self.get(&input).is_some() {
+            return Err(format!("{input} is already given"));
+        }
+        let mut spec = ArchiveSpec::new(&input);
+
+        for arg in args {
+            let (key, value) = match &arg[..] {
+                [key, "=", value @ ..] if !value.is_empty() => (*key, value),
+                _ => return Err("Expected `key = value`".to_owned()),
+            };
+
+            match (key, value) {
+                ("kind", ["text"]) => spec.kind = RecordKind::Bytes,
+                ("kind", ["u64"]) => spec.kind = RecordKind::Unsigned,
+                ("kind", ["i64"]) => spec.kind = RecordKind::Signed,
+                ("delimiter", [lit]) => spec.delimiter = Some(parse_char(lit)?),
+                ("sections", list) => spec.sections = parse_str_list(list)?,
+                ("preload", [lit]) if lit.starts_with('"') => {
+                    spec.preload_file = Some(parse_str(lit)?);
+                }
+                ("preload", list) => spec.preload = parse_str_list(list)?,
+                ("max_entry_len", [lit]) => spec.max_entry_len = Some(parse_usize(lit)?),
+                ("dict_size", [lit]) => spec.dict_size = Some(parse_usize(lit)?),
+                (key, _) => return Err(format!("Unknown option or invalid value for `{key}`")),
+            }
+        }
+
+        self.archives.push(spec);
+        Ok(())
+    }
+}
+
+impl ArchiveSpec {
+    /// A spec for text records, one per line, with no other options.
+    #[must_use]
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.to_owned(),
+            kind: RecordKind::Bytes,
+            delimiter: None,
+            sections: Vec::new(),
+            preload: Vec::new(),
+            preload_file: None,
+            max_entry_len: None,
+            dict_size: None,
+        }
+    }
+
+    /// Options to compress with, starting from `CompressionLevel::Optimal`, as flash is what
+    /// limits how many days fit on the board.
+    #[must_use]
+    pub fn options(&self) -> CompressOptions {
+        let mut options = CompressOptions::new().level(CompressionLevel::Optimal);
+        if let Some(max_entry_len) = self.max_entry_len {
+            options = options.max_entry_len(max_entry_len);
+        }
+        if let Some(dict_size) = self.dict_size {
+            options = options.dict_size(dict_size);
+        }
+        options
+    }
+
+    /// Splits `text` into records as the spec says, after preloading the dictionary with the
+    /// spec's own entries followed by `preload`, which is usually read from `preload_file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message if the number of sections doesn't match the number of lines, or if an
+    /// integer doesn't parse.
+    pub fn writer<'a>(&self, text: &'a str, preload: &[Vec<u8>]) -> Result<Writer<'a>, String> {
+        let mut archive = Writer::with_options(self.options());
+        archive.preload_dict(&self.preload.iter().collect::<Vec<_>>());
+        archive.preload_dict(&preload.iter().collect::<Vec<_>>());
+
+        let lines: Vec<_> = text.lines().collect();
+        if !self.sections.is_empty() && self.sections.len() != lines.len() {
+            return Err(format!(
+                "{} sections given, but {} has {} lines",
+                self.sections.len(),
+                self.input,
+                lines.len()
+            ));
+        }
+
+        for (i, &line) in lines.iter().enumerate() {
+            if let Some(name) = self.sections.get(i) {
+                archive.begin_section(name);
+            }
+
+            let records: Vec<_> = match self.delimiter {
+                Some(delim) => line.split(delim).collect(),
+                None => vec![line],
+            };
+            for record in records {
+                let invalid = || format!("Line {}: invalid integer {record:?}", i + 1);
+                match self.kind {
+                    RecordKind::Bytes => archive.add_record(record),
+                    RecordKind::Unsigned => {
+                        archive.add_u64(record.trim().parse().map_err(|_| invalid())?);
+                    }
+                    RecordKind::Signed => {
+                        archive.add_i64(record.trim().parse().map_err(|_| invalid())?);
+                    }
+                }
+            }
+        }
+
+        Ok(archive)
+    }
+}
+
+// Splits a line into string and character literals, each with its quotes, words, and single
+// punctuation characters.
+fn tokenize(line: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let end = match c {
+            c if c.is_whitespace() => continue,
+            '"' | '\'' => {
+                let mut escaped = false;
+                loop {
+                    match chars.next() {
+                        Some((i, next)) if next == c && !escaped => break i + 1,
+                        Some((_, next)) => escaped = next == '\\' && !escaped,
+                        None => {
+                            return Err(format!("Unterminated literal at column {}", start + 1))
+                        }
+                    }
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => loop {
+                match chars.peek() {
+                    Some(&(_, next)) if next.is_alphanumeric() || next == '_' => {
+                        chars.next();
+                    }
+                    Some(&(i, _)) => break i,
+                    None => break line.len(),
+                }
+            },
+            c => start + c.len_utf8(),
+        };
+        tokens.push(&line[start..end]);
+    }
+
+    Ok(tokens)
+}
+
+// Splits tokens at each comma outside of brackets.
+fn split_commas<'t>(tokens: &[&'t str]) -> Vec<Vec<&'t str>> {
+    let mut args = vec![Vec::new()];
+    let mut depth = 0_usize;
+    for &token in tokens {
+        match token {
+            "," if depth == 0 => args.push(Vec::new()),
+            token => {
+                match token {
+                    "[" => depth += 1,
+                    "]" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                args.last_mut().unwrap().push(token);
+            }
+        }
+    }
+
+    // Allow a trailing comma.
+    if args.last().is_some_and(Vec::is_empty) {
+        args.pop();
+    }
+    args
+}
+
+fn parse_str_list(tokens: &[&str]) -> Result<Vec<String>, String> {
+    let ["[", items @ .., "]"] = tokens else {
+        return Err("Expected a list of strings".to_owned());
+    };
+
+    split_commas(items)
+        .iter()
+        .map(|item| match &item[..] {
+            [lit] => parse_str(lit),
+            _ => Err("Expected a list of strings".to_owned()),
+        })
+        .collect()
+}
+
+fn parse_str(lit: &str) -> Result<String, String> {
+    unquote(lit, '"').ok_or_else(|| format!("Expected a string, found {lit}"))
+}
+
+fn parse_char(lit: &str) -> Result<char, String> {
+    let text = unquote(lit, '\'');
+    let mut chars = text.iter().flat_map(|text| text.chars());
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Expected a character, found {lit}")),
+    }
+}
+
+fn parse_usize(lit: &str) -> Result<usize, String> {
+    let text = lit.replace('_', "");
+    let text = text.strip_suffix("usize").unwrap_or(&text);
+    text.parse()
+        .map_err(|_| format!("Expected an integer, found {lit}"))
+}
+
+/// Returns the contents of a string or character literal as written in Rust source, with any
+/// escapes replaced. Spec files use these too.
+#[must_use]
+pub fn unquote(lit: &str, quote: char) -> Option<String> {
+    // Raw strings have no escapes, just some number of hashes around the quotes.
+    if let Some(raw) = lit.strip_prefix('r').filter(|_| quote == '"') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let inner = raw.get(hashes..raw.len().checked_sub(hashes)?)?;
+        return inner
+            .strip_prefix('"')?
+            .strip_suffix('"')
+            .map(str::to_owned);
+    }
+
+    let inner = lit.strip_prefix(quote)?.strip_suffix(quote)?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                char::from(u8::from_str_radix(&hex, 16).ok().filter(u8::is_ascii)?)
+            }
+            'u' => {
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                char::from_u32(u32::from_str_radix(hex.strip_prefix('{')?, 16).ok()?)?
+            }
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None,
+        };
+        text.push(escaped);
+    }
+
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reader;
+
+    const SPECS: &str = r#"
+        # The shared dictionary.
+        dict = "inputs/dict.bin"
+
+        "inputs/aoc_1901.txt", kind = u64, max_entry_len = 3, preload = "inputs/aoc_1901.preload"
+        "inputs/aoc_1903.txt", delimiter = ',', sections = ["wire 1", "wire 2"], dict_size = 0,
+        "inputs/aoc_1904.txt", preload = ["1", "\"2\""]
+    "#;
+
+    #[test]
+    fn parse_specs() {
+        let specs = ArchiveSpecs::parse(SPECS).unwrap();
+        assert_eq!(specs.dict.as_deref(), Some("inputs/dict.bin"));
+        assert_eq!(specs.archives.len(), 3);
+
+        let day1 = specs.get("inputs/aoc_1901.txt").unwrap();
+        assert_eq!(day1.kind, RecordKind::Unsigned);
+        assert_eq!(day1.max_entry_len, Some(3));
+        assert_eq!(
+            day1.preload_file.as_deref(),
+            Some("inputs/aoc_1901.preload")
+        );
+
+        let day3 = specs.get("inputs/aoc_1903.txt").unwrap();
+        assert_eq!(day3.delimiter, Some(','));
+        assert_eq!(day3.sections, ["wire 1", "wire 2"]);
+        assert_eq!(day3.dict_size, Some(0));
+        assert_eq!(day3.kind, RecordKind::Bytes);
+
+        let day4 = specs.get("inputs/aoc_1904.txt").unwrap();
+        assert_eq!(day4.preload, ["1", "\"2\""]);
+        assert_eq!(day4.preload_file, None);
+
+        assert!(specs.get("inputs/aoc_1902.txt").is_none());
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let errors = [
+            ("kind = u64", "Line 1: Expected the path to an input first"),
+            (
+                "\"a\", kind = u32",
+                "Line 1: Unknown option or invalid value for `kind`",
+            ),
+            ("\"a\", kind", "Line 1: Expected `key = value`"),
+            (
+                "\"a\", delimiter = ',,'",
+                "Line 1: Expected a character, found ',,'",
+            ),
+            (
+                "\"a\", sections = [1]",
+                "Line 1: Expected a string, found 1",
+            ),
+            (
+                "\"a\", dict_size = x1",
+                "Line 1: Expected an integer, found x1",
+            ),
+            (
+                "\"a\", dict = \"b\"",
+                "Line 1: Unknown option or invalid value for `dict`",
+            ),
+            ("\"a\"\n\n\"a\"", "Line 3: a is already given"),
+            (
+                "dict = \"a\"\ndict = \"b\"",
+                "Line 2: The dictionary is already given",
+            ),
+            ("\"a", "Line 1: Unterminated literal at column 1"),
+        ];
+
+        for (text, msg) in errors {
+            assert_eq!(ArchiveSpecs::parse(text), Err(msg.to_owned()), "{text}");
+        }
+    }
+
+    #[test]
+    fn writer_sections() {
+        let spec = ArchiveSpec {
+            delimiter: Some(','),
+            sections: vec!["wire 1".to_owned(), "wire 2".to_owned()],
+            ..ArchiveSpec::new("wires")
+        };
+        let mut archive = Vec::new();
+        spec.writer("R8,U5\nU7,R6,D4\n", &[])
+            .unwrap()
+            .write(&mut archive)
+            .unwrap();
+
+        let mut buf = [0; 8];
+        let reader = Reader::open(&archive).unwrap();
+        let mut wire = reader.section_named("wire 2").unwrap().unwrap();
+        assert_eq!(wire.next_record(&mut buf).unwrap(), Some(&b"U7"[..]));
+        assert_eq!(wire.next_record(&mut buf).unwrap(), Some(&b"R6"[..]));
+        assert_eq!(wire.next_record(&mut buf).unwrap(), Some(&b"D4"[..]));
+        assert_eq!(wire.next_record(&mut buf).unwrap(), None);
+
+        assert_eq!(
+            spec.writer("R8,U5\n", &[]).err().as_deref(),
+            Some("2 sections given, but wires has 1 lines")
+        );
+    }
+
+    #[test]
+    fn writer_integers() {
+        let spec = ArchiveSpec {
+            kind: RecordKind::Signed,
+            max_entry_len: Some(2),
+            ..ArchiveSpec::new("")
+        };
+        let mut archive = Vec::new();
+        spec.writer("12\n -3 \n", &[])
+            .unwrap()
+            .write(&mut archive)
+            .unwrap();
+
+        let mut reader = Reader::open(&archive).unwrap();
+        assert_eq!(reader.next_i64().unwrap(), Some(12));
+        assert_eq!(reader.next_i64().unwrap(), Some(-3));
+        assert_eq!(reader.next_i64().unwrap(), None);
+
+        assert_eq!(
+            spec.writer("12\nx\n", &[]).err().as_deref(),
+            Some("Line 2: invalid integer \"x\"")
+        );
+    }
+
+    #[test]
+    fn unquote_literals() {
+        assert_eq!(unquote(r#""wire 1""#, '"').unwrap(), "wire 1");
+        assert_eq!(unquote(r#""a\n\"\x41\u{e9}""#, '"').unwrap(), "a\n\"Aé");
+        assert_eq!(unquote(r##"r#"a\n"#"##, '"').unwrap(), r"a\n");
+        assert_eq!(unquote(r"','", '\'').unwrap(), ",");
+        assert_eq!(unquote(r"'\''", '\'').unwrap(), "'");
+
+        assert!(unquote("12", '"').is_none());
+        assert!(unquote(r#""\q""#, '"').is_none());
+        assert!(unquote(r#""\xFF""#, '"').is_none());
+    }
+}